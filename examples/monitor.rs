@@ -0,0 +1,175 @@
+// A tiny interactive monitor built on the public API.
+//
+//   load 8000 a9 42 8d 00 02    write bytes
+//   dis 8000 8005               disassemble a range
+//   pc 8000                     jump
+//   step                        run one instruction
+//   run 100                     run for cycles
+//   regs                        show registers
+//   peek 0200 / poke 0200 ff    read and write memory
+use std::io::{self, BufRead, Write};
+
+use emu6502::cpu::{UnknownOpcodePolicy, CPU};
+use emu6502::disasm::disassemble;
+use emu6502::ram::RAM;
+
+struct Monitor {
+    cpu: CPU,
+    ram: RAM,
+}
+
+fn parse_hex(arg: Option<&str>) -> Result<u16, String> {
+    let arg = arg.ok_or_else(|| "missing argument".to_string())?;
+    u16::from_str_radix(arg, 16).map_err(|_| format!("bad hex value: {}", arg))
+}
+
+fn parse_byte(arg: Option<&str>) -> Result<u8, String> {
+    let arg = arg.ok_or_else(|| "missing argument".to_string())?;
+    u8::from_str_radix(arg, 16).map_err(|_| format!("bad hex byte: {}", arg))
+}
+
+impl Monitor {
+    // An opcode that can't run is reported instead of ending the session.
+    fn new() -> Self {
+        let mut cpu = CPU::default();
+        cpu.unknown_opcode_policy = UnknownOpcodePolicy::Error;
+        Self {
+            cpu,
+            ram: RAM::default(),
+        }
+    }
+
+    fn dispatch(&mut self, line: &str) -> Result<String, String> {
+        let mut args = line.split_whitespace();
+        let command = match args.next() {
+            Some(command) => command,
+            None => return Ok("".to_string()),
+        };
+        match command {
+            "load" => {
+                let addr = parse_hex(args.next())?;
+                let bytes = args
+                    .map(|arg| parse_byte(Some(arg)))
+                    .collect::<Result<Vec<_>, _>>()?;
+                if addr as usize + bytes.len() > self.ram.as_slice().len() {
+                    return Err(format!(
+                        "{} bytes at {:04X} run past FFFF",
+                        bytes.len(),
+                        addr
+                    ));
+                }
+                self.ram.write_rom(addr as usize, &bytes);
+                Ok(format!("{} bytes at {:04X}", bytes.len(), addr))
+            }
+            "dis" => {
+                let start = parse_hex(args.next())?;
+                let end = parse_hex(args.next())?;
+                let listing = CPU::disassemble_program(&mut self.ram, start, end, self.cpu.cmos);
+                Ok(listing.trim_end().to_string())
+            }
+            "pc" => {
+                self.cpu.set_pc(parse_hex(args.next())?);
+                Ok(self.regs())
+            }
+            "step" => {
+                let line = disassemble(&mut self.ram, self.cpu.pc(), self.cpu.cmos);
+                self.cpu
+                    .try_step_instruction(&mut self.ram)
+                    .map_err(|e| e.to_string())?;
+                Ok(format!("{}\n{}", line, self.regs()))
+            }
+            "run" => {
+                let cycles = args
+                    .next()
+                    .ok_or_else(|| "missing argument".to_string())?
+                    .parse::<usize>()
+                    .map_err(|e| e.to_string())?;
+                for _ in 0..cycles {
+                    self.cpu.step(&mut self.ram).map_err(|e| e.to_string())?;
+                }
+                Ok(self.regs())
+            }
+            "regs" => Ok(self.regs()),
+            "peek" => {
+                let addr = parse_hex(args.next())?;
+                Ok(format!("{:04X}: {:02X}", addr, self.ram[addr as usize]))
+            }
+            "poke" => {
+                let addr = parse_hex(args.next())?;
+                let byte = parse_byte(args.next())?;
+                self.ram[addr as usize] = byte;
+                Ok(format!("{:04X}: {:02X}", addr, byte))
+            }
+            _ => Err(format!("unknown command: {}", command)),
+        }
+    }
+
+    fn regs(&self) -> String {
+        let mut regs = self.cpu.registers();
+        format!(
+            "PC:{:04X} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} CYC:{}",
+            regs.pc,
+            regs.a,
+            regs.x,
+            regs.y,
+            regs.flags.get_as_u8(),
+            regs.sp,
+            self.cpu.total_cycles
+        )
+    }
+}
+
+fn main() {
+    let mut monitor = Monitor::new();
+    let stdin = io::stdin();
+    print!("> ");
+    io::stdout().flush().unwrap();
+    for line in stdin.lock().lines() {
+        let line = line.unwrap();
+        if line.trim() == "quit" {
+            break;
+        }
+        match monitor.dispatch(&line) {
+            Ok(output) => println!("{}", output),
+            Err(message) => println!("? {}", message),
+        }
+        print!("> ");
+        io::stdout().flush().unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dispatch() {
+        let mut monitor = Monitor::new();
+        let mut run = |line: &str| monitor.dispatch(line);
+
+        assert_eq!(run("load 8000 a9 42 8d 00 02").unwrap(), "5 bytes at 8000");
+        assert_eq!(
+            run("dis 8000 8005").unwrap(),
+            "8000  A9 42     LDA #$42\n8002  8D 00 02  STA $0200"
+        );
+        run("pc 8000").unwrap();
+        assert!(run("step")
+            .unwrap()
+            .starts_with("8000  A9 42     LDA #$42\nPC:8002 A:42"));
+        assert!(run("run 4").unwrap().starts_with("PC:8005"));
+        assert_eq!(run("peek 0200").unwrap(), "0200: 42");
+        assert_eq!(run("poke 0200 ff").unwrap(), "0200: FF");
+        assert_eq!(run("peek 0200").unwrap(), "0200: FF");
+        assert!(run("frobnicate").is_err());
+        assert!(run("peek zz").is_err());
+        assert!(run("load 8000 a9 142").is_err());
+        assert!(run("poke 0200 1ff").is_err());
+        assert!(run("load ffff a9 42").is_err());
+        assert_eq!(run("load ffff a9").unwrap(), "1 bytes at FFFF");
+
+        run("load 8000 ab").unwrap();
+        run("pc 8000").unwrap();
+        assert_eq!(run("step").unwrap_err(), "unknown opcode 0xAB");
+        assert_eq!(run("run 10").unwrap_err(), "unknown opcode 0xAB");
+    }
+}
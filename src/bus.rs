@@ -0,0 +1,204 @@
+use std::io::Write;
+use std::ops::Range;
+
+use crate::ram::MemIO;
+use crate::reset::Reset;
+
+// A memory-mapped device. Addresses are passed as-is, not relative to the mapping.
+pub trait Device {
+    fn read(&mut self, address: usize) -> u8;
+    fn write(&mut self, address: usize, byte: u8);
+}
+
+// A character output port: bytes written to it go to `out`, reads return 0.
+// Write errors are dropped, as the guest has no way to see them.
+pub struct Console {
+    out: Box<dyn Write>,
+}
+
+impl Console {
+    pub fn new(out: Box<dyn Write>) -> Self {
+        Self { out }
+    }
+}
+
+impl Device for Console {
+    fn read(&mut self, _address: usize) -> u8 {
+        0
+    }
+
+    fn write(&mut self, _address: usize, byte: u8) {
+        let _ = self.out.write_all(&[byte]);
+    }
+}
+
+// Routes accesses to mapped devices and falls back to the inner memory otherwise.
+pub struct Bus<M: MemIO> {
+    mem: M,
+    devices: Vec<(Range<usize>, Box<dyn Device>)>,
+    write_only: Vec<Range<usize>>,
+    open_bus: u8, // last value seen on the data bus
+}
+
+impl<M: MemIO> Bus<M> {
+    pub fn new(mem: M) -> Self {
+        Self {
+            mem,
+            devices: vec![],
+            write_only: vec![],
+            open_bus: 0,
+        }
+    }
+
+    pub fn map(&mut self, range: Range<usize>, device: Box<dyn Device>) {
+        self.devices.push((range, device));
+    }
+
+    // Reads of a write-only range return the open bus value without touching the device.
+    pub fn map_write_only(&mut self, range: Range<usize>, device: Box<dyn Device>) {
+        self.write_only.push(range.clone());
+        self.map(range, device);
+    }
+
+    pub fn mem(&mut self) -> &mut M {
+        &mut self.mem
+    }
+
+    fn device(&mut self, address: usize) -> Option<&mut Box<dyn Device>> {
+        self.devices
+            .iter_mut()
+            .find(|(range, _)| range.contains(&address))
+            .map(|(_, device)| device)
+    }
+
+    fn is_write_only(&self, address: usize) -> bool {
+        self.write_only.iter().any(|range| range.contains(&address))
+    }
+}
+
+impl<M: MemIO> MemIO for Bus<M> {
+    fn read_byte(&mut self, address: usize) -> u8 {
+        if self.is_write_only(address) {
+            return self.open_bus;
+        }
+        let byte = match self.device(address) {
+            Some(device) => device.read(address),
+            None => self.mem.read_byte(address),
+        };
+        self.open_bus = byte;
+        byte
+    }
+
+    fn read_byte_without_effect(&mut self, address: usize) -> u8 {
+        if self.is_write_only(address) {
+            return self.open_bus;
+        }
+        match self.device(address) {
+            Some(device) => device.read(address),
+            None => self.mem.read_byte_without_effect(address),
+        }
+    }
+
+    fn write_byte(&mut self, address: usize, byte: u8) {
+        self.open_bus = byte;
+        match self.device(address) {
+            Some(device) => device.write(address, byte),
+            None => self.mem.write_byte(address, byte),
+        }
+    }
+
+    fn is_uninitialized(&self, address: usize) -> bool {
+        let mapped = self
+            .devices
+            .iter()
+            .any(|(range, _)| range.contains(&address));
+        !mapped && self.mem.is_uninitialized(address)
+    }
+}
+
+impl<M: MemIO + Reset> Reset for Bus<M> {
+    fn reset(&mut self) {
+        self.mem.reset();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::CPU;
+    use crate::ram::RAM;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    struct Register(u8);
+
+    impl Device for Register {
+        fn read(&mut self, _address: usize) -> u8 {
+            self.0
+        }
+
+        fn write(&mut self, _address: usize, byte: u8) {
+            self.0 = byte;
+        }
+    }
+
+    #[test]
+    fn test_device() {
+        let mut bus = Bus::new(RAM::default());
+        bus.map(0x2000..0x2001, Box::new(Register(0x42)));
+        assert_eq!(bus.read_byte(0x2000), 0x42);
+        bus.write_byte(0x2000, 0x10);
+        assert_eq!(bus.read_byte(0x2000), 0x10);
+        assert_eq!(bus.mem()[0x2000], 0x00);
+    }
+
+    #[test]
+    fn test_write_only() {
+        let mut bus = Bus::new(RAM::default());
+        bus.map_write_only(0x2000..0x2001, Box::new(Register(0)));
+        bus.mem()[0x10] = 0x84;
+
+        bus.write_byte(0x2000, 0x42);
+        assert_eq!(bus.read_byte(0x10), 0x84);
+        assert_eq!(bus.read_byte(0x2000), 0x84);
+    }
+
+    #[test]
+    fn test_console() {
+        #[derive(Clone, Default)]
+        struct Buffer(Rc<RefCell<Vec<u8>>>);
+
+        impl Write for Buffer {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.borrow_mut().extend_from_slice(buf);
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let output = Buffer::default();
+        let mut bus = Bus::new(RAM::default());
+        bus.map(
+            0x6000..0x6001,
+            Box::new(Console::new(Box::new(output.clone()))),
+        );
+        bus.mem().write_rom(
+            0x8000,
+            &[
+                0xA9, 0x48, //       LDA #'H'
+                0x8D, 0x00, 0x60, // STA $6000
+                0xA9, 0x69, //       LDA #'i'
+                0x8D, 0x00, 0x60, // STA $6000
+            ],
+        );
+        let mut cpu = CPU::default();
+        cpu.pc = 0x8000;
+        for _ in 0..4 {
+            cpu.step_instruction(&mut bus);
+        }
+        assert_eq!(*output.0.borrow(), b"Hi");
+    }
+}
@@ -1,9 +1,27 @@
-use crate::instruction::{OpCode, OPCODES};
-use crate::ram::MemIO;
+use std::collections::VecDeque;
+use std::ops::Range;
+use std::time::Instant;
+
+use crate::disasm::{disassemble, DisasmLine};
+use crate::instruction::{decode, OpCode, OPCODES};
+use crate::ram::{MemError, MemIO};
 use crate::reset::Reset;
 
+// The 6502 is little-endian: the low byte of a word comes first.
+pub fn lo(word: u16) -> u8 {
+    (word & 0xFF) as u8
+}
+
+pub fn hi(word: u16) -> u8 {
+    (word >> 8) as u8
+}
+
+pub fn make_word(lo: u8, hi: u8) -> u16 {
+    ((hi as u16) << 8) | lo as u16
+}
+
 // http://www.obelisk.me.uk/6502/registers.html
-#[derive(Debug, Default, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub struct CPU {
     pub pc: u16, // Program Counter
     pub sp: u8,  // Stack Pointer, it uses as lower byte on "0x01XX".
@@ -16,20 +34,333 @@ pub struct CPU {
 
     pub remain_cycles: usize,
     pub total_cycles: usize,
+    pub cycle_debt: usize, // Cycles run past the end of the last frame
+    total_instructions: u64,
+
+    trace_range: Option<(u16, u16)>, // Only trace while PC is in start..end
+    recent_trace: VecDeque<(u16, u8)>, // (pc, opcode byte) of the last instructions fetched
+    recent_trace_len: usize,
+
+    pub detect_self_modifying: bool, // Warn on writes into recently executed code
+    recent_instructions: [(u16, u8); RECENT_INSTRUCTIONS], // (address, length)
+    recent_index: usize,
+    warning: Option<CpuWarning>,
+    instruction_pc: u16,           // address of the instruction being executed
+    pub(crate) index_fix_up: bool, // the instruction reads the address before the index carry
+    zp_watches: Vec<u8>,
+    zp_write: Option<(u8, u16)>, // (address, pc) of the last write to a watched zero-page byte
+
+    // The B flag only exists in pushed status bytes. When strict, `step` panics if an
+    // instruction or the illegal handler leaves it set in the live register, where
+    // reading it would be a bug.
+    pub strict_b_flag: bool,
+
+    irq_line: bool,
+    irq_pending: bool, // IRQ seen by the last poll, taken before the next opcode fetch
+    nmi_pending: bool, // NMI edge seen, taken before the next opcode fetch
+
+    // Set by a JAM opcode. Steps do nothing until the next reset.
+    pub halted: bool,
+
+    // When false, unofficial opcodes are treated like undefined ones
+    pub allow_unofficial: bool,
+
+    // What `step` does with an opcode that can't run and no illegal handler is set
+    pub unknown_opcode_policy: UnknownOpcodePolicy,
+
+    pub cmos: bool, // Decode the opcodes added by the 65C02
+
+    // ANE computes A = (A | magic) & X & imm, where magic varies between chips
+    pub ane_magic: u8,
+
+    // Address of the NMI vector, followed by the reset and IRQ/BRK vectors. 0xFFFA on a 6502.
+    pub vector_base: u16,
+
+    // Record every bus access of the last instruction, see `bus_cycles`
+    pub capture_bus: bool,
+    bus_cycles: Vec<BusCycle>,
+
+    hooks: HostHooks,
+}
+
+// Closures installed by the host. They belong to one CPU: clones start without any.
+#[derive(Default)]
+struct HostHooks {
+    cycle_callback: Option<Box<dyn FnMut(usize)>>,
+    watches: Vec<WatchExpr>,
+    illegal_handler: Option<Box<IllegalFn>>,
+}
+
+type IllegalFn = dyn FnMut(&mut CPU, &mut dyn MemIO, u8);
+
+impl std::fmt::Debug for HostHooks {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HostHooks")
+            .field("cycle_callback", &self.cycle_callback.is_some())
+            .field("watches", &self.watches.len())
+            .field("illegal_handler", &self.illegal_handler.is_some())
+            .finish()
+    }
+}
+
+impl Clone for HostHooks {
+    fn clone(&self) -> Self {
+        HostHooks::default()
+    }
+}
+
+// Compares the same fields `diff` reports, so `a != b` always comes with a reason.
+// Debugging and host state (halted, traces, warnings, ...) is left out.
+impl PartialEq for CPU {
+    fn eq(&self, other: &Self) -> bool {
+        self.pc == other.pc
+            && self.sp == other.sp
+            && self.a == other.a
+            && self.x == other.x
+            && self.y == other.y
+            && self.flags == other.flags
+            && self.remain_cycles == other.remain_cycles
+            && self.total_cycles == other.total_cycles
+    }
+}
+
+impl Eq for CPU {}
+
+// A condition checked after each instruction by `run_with_watches`
+pub struct WatchExpr(Box<WatchFn>);
+
+type WatchFn = dyn FnMut(&CPU, &mut dyn MemIO) -> bool;
+
+impl WatchExpr {
+    pub fn new(condition: impl FnMut(&CPU, &mut dyn MemIO) -> bool + 'static) -> Self {
+        WatchExpr(Box::new(condition))
+    }
+}
+
+impl Default for CPU {
+    fn default() -> Self {
+        CPU {
+            pc: 0,
+            sp: 0,
+            a: 0,
+            x: 0,
+            y: 0,
+            flags: StatusFlag::default(),
+            remain_cycles: 0,
+            total_cycles: 0,
+            cycle_debt: 0,
+            total_instructions: 0,
+            trace_range: None,
+            recent_trace: VecDeque::new(),
+            recent_trace_len: 0,
+            detect_self_modifying: false,
+            recent_instructions: [(0, 0); RECENT_INSTRUCTIONS],
+            recent_index: 0,
+            warning: None,
+            instruction_pc: 0,
+            zp_watches: vec![],
+            zp_write: None,
+            strict_b_flag: false,
+            irq_line: false,
+            irq_pending: false,
+            nmi_pending: false,
+            halted: false,
+            index_fix_up: false,
+            allow_unofficial: true,
+            unknown_opcode_policy: UnknownOpcodePolicy::Panic,
+            cmos: false,
+            ane_magic: 0xFF,
+            vector_base: 0xFFFA,
+            capture_bus: false,
+            bus_cycles: vec![],
+            hooks: HostHooks::default(),
+        }
+    }
+}
+
+const RECENT_INSTRUCTIONS: usize = 8;
+
+pub const RESET_CYCLES: usize = 7;
+
+// How many instructions `run_with_deadline` runs between looks at the clock
+const DEADLINE_CHECK_INTERVAL: usize = 1024;
+
+// Suspicious but legal guest behavior, reported for debugging
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum CpuWarning {
+    SelfModifyingCode { addr: u16 },
+    UninitializedRead { addr: u16, pc: u16 }, // pc of the reading instruction
+}
+
+// Snapshot of the programmer-visible registers
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Registers {
+    pub pc: u16,
+    pub sp: u8,
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub flags: StatusFlag,
+}
+
+impl std::fmt::Display for Registers {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut flags = self.flags;
+        write!(
+            f,
+            "PC:{:04X} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X}",
+            self.pc,
+            self.a,
+            self.x,
+            self.y,
+            flags.get_as_u8(),
+            self.sp
+        )
+    }
+}
+
+// Why `step` couldn't run an instruction, or `call_subroutine` couldn't finish
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum StepError {
+    UnknownOpcode(u8), // undefined, or unofficial while `allow_unofficial` is off
+    Timeout(usize),    // still running after this many cycles
+}
+
+impl std::fmt::Display for StepError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StepError::UnknownOpcode(op) => write!(f, "unknown opcode {:#04X}", op),
+            StepError::Timeout(cycles) => write!(f, "didn't return within {} cycles", cycles),
+        }
+    }
+}
+
+impl std::error::Error for StepError {}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum UnknownOpcodePolicy {
+    Panic,
+    Nop,   // skip the byte in two cycles, like an implied instruction
+    Halt,  // stop as a JAM opcode would, with PC left on the byte
+    Error, // return `StepError::UnknownOpcode` with PC left on the byte
+}
+
+// Why a run loop gave control back
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum StopReason {
+    Timeout,
+    WatchExpr(usize),                    // index of the watch that fired
+    ZeroPageWrite { addr: u8, pc: u16 }, // `watch_zp` address written by the instruction at `pc`
+    Flag(Flag),                          // the flag passed to `run_until_flag` reached its value
+    Halted,                              // a JAM opcode stopped the CPU
+    Error(StepError),                    // an opcode couldn't run, see `unknown_opcode_policy`
+}
+
+// Everything about a single executed instruction
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct StepResult {
+    pub pc_before: u16,
+    pub opcode: Option<OpCode>, // None for a byte that couldn't run as an opcode
+    pub bytes: Vec<u8>,
+    pub cycles: usize,
+    pub regs_after: Registers,
+}
+
+// One bus access, in the shape of the cycle records of SingleStepTests
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BusCycle {
+    pub addr: u16,
+    pub value: u8,
+    pub is_write: bool,
+}
+
+// Passes accesses through to the inner memory, logging them as bus cycles.
+struct BusRecorder<'a, T: MemIO> {
+    mem: &'a mut T,
+    cycles: Vec<BusCycle>,
+}
+
+impl<'a, T: MemIO> BusRecorder<'a, T> {
+    fn record(&mut self, address: usize, value: u8, is_write: bool) {
+        self.cycles.push(BusCycle {
+            addr: address as u16,
+            value,
+            is_write,
+        });
+    }
+}
+
+impl<'a, T: MemIO> MemIO for BusRecorder<'a, T> {
+    fn read_byte(&mut self, address: usize) -> u8 {
+        let value = self.mem.read_byte(address);
+        self.record(address, value, false);
+        value
+    }
+
+    fn read_byte_without_effect(&mut self, address: usize) -> u8 {
+        self.mem.read_byte_without_effect(address)
+    }
+
+    fn write_byte(&mut self, address: usize, byte: u8) {
+        self.mem.write_byte(address, byte);
+        self.record(address, byte, true);
+    }
+
+    fn try_read_byte(&mut self, address: usize) -> Result<u8, MemError> {
+        let value = self.mem.try_read_byte(address)?;
+        self.record(address, value, false);
+        Ok(value)
+    }
+
+    fn try_write_byte(&mut self, address: usize, byte: u8) -> Result<(), MemError> {
+        self.mem.try_write_byte(address, byte)?;
+        self.record(address, byte, true);
+        Ok(())
+    }
+
+    fn is_uninitialized(&self, address: usize) -> bool {
+        self.mem.is_uninitialized(address)
+    }
+}
+
+// One line of an instruction trace: the instruction and the state after it ran
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TraceEntry {
+    pub pc: u16,
+    pub opcode: Option<OpCode>, // as in `StepResult`
+    pub text: String,           // disassembly, e.g. "LDA #$42"
+    pub registers: Registers,
+    pub total_cycles: usize,
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct StatusFlag {
     pub c: bool, // Carry Flag
     pub z: bool, // Zero Flag
     pub i: bool, // Interrupt Disable
     pub d: bool, // Decimal Mode
     pub b: bool, // Break Command
-    pub r: bool, // Reserved (Unused, always true)
+    pub r: bool, // Reserved (Unused, always reads as 1)
     pub v: bool, // Overflow Flag
     pub n: bool, // Negative Flag
 }
 
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Flag {
+    C,
+    Z,
+    I,
+    D,
+    B,
+    V,
+    N,
+}
+
 impl Default for StatusFlag {
     fn default() -> Self {
         StatusFlag {
@@ -55,8 +386,9 @@ pub enum Interrupt {
 }
 
 impl CPU {
+    // Clears every register and runs the reset sequence, for a known starting state.
+    // `power_on` and `soft_reset` model what the hardware leaves behind instead.
     pub fn reset<T: Reset + MemIO>(&mut self, ram: &mut T) {
-        self.pc = 0xFFFC;
         self.sp = 0xFF;
         self.flags.c = false;
         self.flags.z = false;
@@ -68,71 +400,412 @@ impl CPU {
         self.a = 0;
         self.x = 0;
         self.y = 0;
+        self.reset_sequence(ram);
+    }
+
+    // Registers start out cleared, then the reset sequence takes SP from 0x00 to 0xFD
+    // and sets I. RAM is left alone; `RAM::randomize` models its unknown contents.
+    pub fn power_on<T: Reset + MemIO>(&mut self, ram: &mut T) {
+        self.sp = 0;
+        self.flags = StatusFlag::default();
+        self.a = 0;
+        self.x = 0;
+        self.y = 0;
+        self.soft_reset(ram);
+    }
 
-        let addr_low = self.fetch_byte(ram);
-        let addr_high = self.fetch_byte(ram);
-        self.pc = ((addr_high as u16) << 8) + (addr_low as u16);
+    // A pulse on the reset pin: A, X, Y and most flags survive, the suppressed pushes
+    // take SP down by 3 and I is set. The 65C02 also clears D.
+    pub fn soft_reset<T: Reset + MemIO>(&mut self, ram: &mut T) {
+        self.sp = self.sp.wrapping_sub(3);
+        self.flags.i = true;
+        if self.cmos {
+            self.flags.d = false;
+        }
+        self.reset_sequence(ram);
+    }
+
+    // Reset takes 7 cycles like an interrupt, but only the vector reads touch memory.
+    // Cycle counting starts over, with the reset sequence itself counted like nestest's
+    // CYC:7 at the first instruction.
+    // Safe to call at any cycle: the rest of an instruction in progress is dropped along
+    // with any interrupt latched during it. Its bus accesses were made on its first cycle
+    // and are not undone.
+    fn reset_sequence<T: Reset + MemIO>(&mut self, ram: &mut T) {
+        self.total_cycles = 0;
+        self.total_instructions = 0;
+        self.cycle_debt = 0;
+        self.irq_pending = false;
+        self.nmi_pending = false;
+        self.halted = false;
+        self.remain_cycles = RESET_CYCLES - 2; // internal cycles and suppressed pushes
+        self.pc = self.read_vector(ram, self.vector_base.wrapping_add(2));
+        self.count_cycles(self.remain_cycles);
 
         ram.reset();
     }
 
+    // Takes an interrupt at an instruction boundary. Non-reset interrupts cost 7 cycles,
+    // which are queued on `remain_cycles` and counted in `total_cycles` right away.
     pub fn interrupt<T: MemIO>(&mut self, ram: &mut T, kind: Interrupt) {
+        self.enter_interrupt(ram, kind);
+    }
+
+    // The single entry path for interrupts taken between instructions.
+    fn enter_interrupt<T: MemIO>(&mut self, ram: &mut T, kind: Interrupt) {
+        let before = self.remain_cycles;
+        self.handle_interrupt(ram, kind);
+        self.count_cycles(self.remain_cycles - before);
+    }
+
+    // Shared by `interrupt` and the BRK instruction, whose opcode fetch is already counted.
+    pub(crate) fn handle_interrupt<T: MemIO>(&mut self, ram: &mut T, kind: Interrupt) {
         if Interrupt::IRQ == kind && self.flags.i {
             return;
         }
         if Interrupt::Reset != kind {
             if Interrupt::BRK != kind {
-                self.flags.b = false;
+                // the opcode fetch is forced to BRK, then PC is read again
+                self.dummy_read(ram, self.pc);
+                self.dummy_read(ram, self.pc);
+            } else {
+                // BRK skips the padding byte after it, so RTI returns past both bytes
+                self.dummy_read(ram, self.pc);
+                self.pc = self.pc.wrapping_add(1);
             }
-            self.flags.r = true;
-            self.push_to_stack(ram, (self.pc >> 8) as u8);
-            self.push_to_stack(ram, (self.pc & 0xFF) as u8);
-            let flag_status = self.flags.get_as_u8();
-            self.push_to_stack(ram, flag_status);
+            self.stack_write(ram, hi(self.pc));
+            self.stack_write(ram, lo(self.pc));
+            // The B flag only exists in the pushed byte, set for BRK and clear otherwise
+            let b_flag = if Interrupt::BRK == kind {
+                0b00010000
+            } else {
+                0
+            };
+            let flag_status = (self.flags.get_as_u8() & !0b00010000) | b_flag;
+            self.stack_write(ram, flag_status);
             self.flags.i = true;
         }
 
-        self.pc = match kind {
-            Interrupt::NMI => 0xFFFA,
-            Interrupt::Reset => 0xFFFC,
-            Interrupt::IRQ => 0xFFFE,
-            Interrupt::BRK => 0xFFFE,
+        let offset = match kind {
+            Interrupt::NMI => 0,
+            Interrupt::Reset => 2,
+            Interrupt::IRQ => 4,
+            Interrupt::BRK => 4,
         };
+        self.pc = self.read_vector(ram, self.vector_base.wrapping_add(offset));
+    }
 
-        let addr_low = self.fetch_byte(ram);
-        let addr_high = self.fetch_byte(ram);
-        self.pc = ((addr_high as u16) << 8) + (addr_low as u16);
+    // A plain little-endian word: the high byte is read from `addr + 1` even across a page,
+    // as vector fetches don't share the page wrap of JMP ($xxFF).
+    fn read_vector<T: MemIO>(&mut self, ram: &mut T, addr: u16) -> u16 {
+        let addr_low = self.read_byte(ram, addr as usize);
+        let addr_high = self.read_byte(ram, addr.wrapping_add(1) as usize);
+        make_word(addr_low, addr_high)
     }
 
-    pub fn fetch_byte<T: MemIO>(&mut self, ram: &mut T) -> u8 {
+    pub fn fetch_byte<T: MemIO + ?Sized>(&mut self, ram: &mut T) -> u8 {
         let byte = ram.read_byte(self.pc as usize);
         self.pc = self.pc.wrapping_add(1);
         self.remain_cycles += 1;
         byte
     }
 
-    pub fn read_byte<T: MemIO>(&mut self, ram: &mut T, addr: usize) -> u8 {
+    pub fn read_byte<T: MemIO + ?Sized>(&mut self, ram: &mut T, addr: usize) -> u8 {
+        if ram.is_uninitialized(addr) {
+            self.warning = Some(CpuWarning::UninitializedRead {
+                addr: addr as u16,
+                pc: self.instruction_pc,
+            });
+        }
         let byte = ram.read_byte(addr);
         self.remain_cycles += 1;
         byte
     }
 
-    pub fn write_byte<T: MemIO>(&mut self, ram: &mut T, addr: usize, byte: u8) {
+    pub fn write_byte<T: MemIO + ?Sized>(&mut self, ram: &mut T, addr: usize, byte: u8) {
+        if self.detect_self_modifying && self.is_recently_executed(addr as u16) {
+            self.warning = Some(CpuWarning::SelfModifyingCode { addr: addr as u16 });
+        }
+        self.check_zp_watch(addr);
         ram.write_byte(addr, byte);
         self.remain_cycles += 1;
     }
 
+    // Cycles whose bus access the CPU ignores. They don't count as reads of uninitialized
+    // memory or writes to watched bytes.
+    pub(crate) fn dummy_read<T: MemIO + ?Sized>(&mut self, ram: &mut T, addr: u16) {
+        ram.read_byte(addr as usize);
+        self.remain_cycles += 1;
+    }
+
+    pub(crate) fn dummy_write<T: MemIO + ?Sized>(&mut self, ram: &mut T, addr: u16, byte: u8) {
+        ram.write_byte(addr as usize, byte);
+        self.remain_cycles += 1;
+    }
+
+    // Like read_byte and write_byte, but pass memory errors on to the caller.
+    pub fn try_read_byte<T: MemIO>(&mut self, ram: &mut T, addr: usize) -> Result<u8, MemError> {
+        let byte = ram.try_read_byte(addr)?;
+        self.remain_cycles += 1;
+        Ok(byte)
+    }
+
+    pub fn try_write_byte<T: MemIO>(
+        &mut self,
+        ram: &mut T,
+        addr: usize,
+        byte: u8,
+    ) -> Result<(), MemError> {
+        ram.try_write_byte(addr, byte)?;
+        self.check_zp_watch(addr);
+        self.remain_cycles += 1;
+        Ok(())
+    }
+
+    fn check_zp_watch(&mut self, addr: usize) {
+        if addr < 0x100 && self.zp_watches.contains(&(addr as u8)) {
+            self.zp_write = Some((addr as u8, self.instruction_pc));
+        }
+    }
+
+    // The bus accesses of the last instruction run with `capture_bus` set, in order,
+    // one per cycle. Internal cycles show up as the dummy reads and writes they make.
+    pub fn bus_cycles(&self) -> &[BusCycle] {
+        &self.bus_cycles
+    }
+
+    // Returns the latest warning and clears it.
+    pub fn take_warning(&mut self) -> Option<CpuWarning> {
+        self.warning.take()
+    }
+
+    fn record_instruction(&mut self, addr: u16, op: &OpCode) {
+        self.recent_instructions[self.recent_index] = (addr, 1 + op.1.operand_len() as u8);
+        self.recent_index = (self.recent_index + 1) % RECENT_INSTRUCTIONS;
+    }
+
+    fn is_recently_executed(&self, addr: u16) -> bool {
+        self.recent_instructions
+            .iter()
+            .any(|&(start, len)| addr.wrapping_sub(start) < len as u16)
+    }
+
+    // Like PHA, reads the byte after the opcode before the write.
     pub fn push_to_stack<T: MemIO>(&mut self, ram: &mut T, byte: u8) {
+        self.dummy_read(ram, self.pc);
+        self.stack_write(ram, byte);
+    }
+
+    // Sets up the stack as if a JSR had been made, so the next RTS returns to `addr`.
+    // Meant for test setup, so it doesn't consume cycles.
+    pub fn push_return_address<T: MemIO>(&mut self, ram: &mut T, addr: u16) {
+        let addr = addr.wrapping_sub(1);
+        for byte in [hi(addr), lo(addr)].iter() {
+            ram.write_byte((0x0100 + self.sp as u16) as usize, *byte);
+            self.sp = self.sp.wrapping_sub(1);
+        }
+    }
+
+    // A push costing only the write cycle itself
+    pub(crate) fn stack_write<T: MemIO>(&mut self, ram: &mut T, byte: u8) {
         self.write_byte(ram, (0x0100 + self.sp as u16) as usize, byte);
         self.sp = self.sp.wrapping_sub(1);
-        self.remain_cycles += 1;
     }
 
+    // Reads the top of the stack once while SP is incremented, then pulls.
     pub fn pull_from_stack<T: MemIO>(&mut self, ram: &mut T) -> u8 {
+        self.dummy_read(ram, 0x0100 + self.sp as u16);
+        self.stack_read(ram)
+    }
+
+    // A pull costing only the read cycle itself, the SP increment being left to the caller
+    pub(crate) fn stack_read<T: MemIO>(&mut self, ram: &mut T) -> u8 {
         self.sp = self.sp.wrapping_add(1);
-        let byte = self.read_byte(ram, (0x0100 + self.sp as u16) as usize);
-        self.remain_cycles += 1;
-        byte
+        self.read_byte(ram, (0x0100 + self.sp as u16) as usize)
+    }
+
+    pub fn registers(&self) -> Registers {
+        Registers {
+            pc: self.pc,
+            sp: self.sp,
+            a: self.a,
+            x: self.x,
+            y: self.y,
+            flags: self.flags,
+        }
+    }
+
+    pub fn set_registers(&mut self, registers: Registers) {
+        self.pc = registers.pc;
+        self.sp = registers.sp;
+        self.a = registers.a;
+        self.x = registers.x;
+        self.y = registers.y;
+        self.flags = registers.flags;
+    }
+
+    // Copies PC, SP, A, X, Y and the flags into `other`, leaving its counters and settings alone.
+    pub fn clone_state_into(&self, other: &mut CPU) {
+        other.set_registers(self.registers());
+    }
+
+    // The registers, the bytes on the stack from the top down and a hexdump of the zero page,
+    // for pasting into a bug report. Reads have no side effects.
+    pub fn dump_state<T: MemIO>(&self, mem: &mut T) -> String {
+        let mut dump = format!("{}\n", self.registers());
+        dump += "Stack:";
+        for sp in self.sp as usize + 1..=0xFF {
+            dump += &format!(" {:02X}", mem.read_byte_without_effect(0x0100 + sp));
+        }
+        dump += "\nZero page:\n";
+        for row in (0..0x100).step_by(0x10) {
+            let bytes = (row..row + 0x10)
+                .map(|addr| format!("{:02X}", mem.read_byte_without_effect(addr)))
+                .collect::<Vec<_>>()
+                .join(" ");
+            dump += &format!("{:04X}  {}\n", row, bytes);
+        }
+        dump
+    }
+
+    // Lists the registers and counters that differ from `other`, as "name: self != other".
+    // Empty exactly when `self == other`.
+    pub fn diff(&self, other: &CPU) -> Vec<String> {
+        let mut diffs = vec![];
+        let mut check = |name: &str, left: String, right: String| {
+            if left != right {
+                diffs.push(format!("{}: {} != {}", name, left, right));
+            }
+        };
+        check(
+            "pc",
+            format!("{:04X}", self.pc),
+            format!("{:04X}", other.pc),
+        );
+        check(
+            "sp",
+            format!("{:02X}", self.sp),
+            format!("{:02X}", other.sp),
+        );
+        check("a", format!("{:02X}", self.a), format!("{:02X}", other.a));
+        check("x", format!("{:02X}", self.x), format!("{:02X}", other.x));
+        check("y", format!("{:02X}", self.y), format!("{:02X}", other.y));
+        check(
+            "flags",
+            format!("{:?}", self.flags),
+            format!("{:?}", other.flags),
+        );
+        check(
+            "remain_cycles",
+            self.remain_cycles.to_string(),
+            other.remain_cycles.to_string(),
+        );
+        check(
+            "total_cycles",
+            self.total_cycles.to_string(),
+            other.total_cycles.to_string(),
+        );
+        diffs
+    }
+
+    // Instructions executed since the last reset, not counting interrupt sequences
+    pub fn total_instructions(&self) -> u64 {
+        self.total_instructions
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    // Redirects execution. Any cycles left of the current instruction are dropped,
+    // so the next `step` fetches an opcode from `addr`.
+    pub fn set_pc(&mut self, addr: u16) {
+        self.pc = addr;
+        self.remain_cycles = 0;
+    }
+
+    // A taken branch reads the next opcode while it adds the offset to PCL, and on a page
+    // cross reads once more from the target with PCH not yet fixed up.
+    pub(crate) fn take_branch<T: MemIO>(&mut self, ram: &mut T, addr: u16) {
+        self.dummy_read(ram, self.pc);
+        if self.pc & 0xFF00 != addr & 0xFF00 {
+            self.dummy_read(ram, make_word(lo(addr), hi(self.pc)));
+        }
+        self.pc = addr;
+    }
+
+    // Keeps the PC and opcode byte of the last `len` fetched instructions, including one
+    // that fails to decode, for looking back after a crash. 0 disables the record.
+    pub fn set_recent_trace_len(&mut self, len: usize) {
+        self.recent_trace_len = len;
+        while self.recent_trace.len() > len {
+            self.recent_trace.pop_front();
+        }
+    }
+
+    // Oldest first.
+    pub fn recent_trace(&self) -> Vec<(u16, u8)> {
+        self.recent_trace.iter().copied().collect()
+    }
+
+    // Debugger access to little-endian words, without side effects or cycles.
+    pub fn peek_word<T: MemIO>(&self, mem: &mut T, addr: u16) -> u16 {
+        make_word(
+            mem.read_byte_without_effect(addr as usize),
+            mem.read_byte_without_effect(addr.wrapping_add(1) as usize),
+        )
+    }
+
+    pub fn poke_word<T: MemIO>(&self, mem: &mut T, addr: u16, word: u16) {
+        mem.write_byte(addr as usize, lo(word));
+        mem.write_byte(addr.wrapping_add(1) as usize, hi(word));
+    }
+
+    // The address the next instruction will access, resolved without executing it.
+    // It doesn't move PC or consume cycles.
+    pub fn effective_address<T: MemIO>(&self, mem: &mut T) -> Option<u16> {
+        let op = decode(mem.read_byte_without_effect(self.pc as usize), self.cmos)?;
+        op.1.peek_address(self, mem, self.pc.wrapping_add(1))
+    }
+
+    // A monitor-style listing of the instructions in start..end, one per line.
+    // `cmos` picks the 65C02 decoding, as `CPU::cmos` does when running.
+    pub fn disassemble_program<T: MemIO>(mem: &mut T, start: u16, end: u16, cmos: bool) -> String {
+        let mut listing = String::new();
+        let mut addr = start as usize;
+        while addr < end as usize {
+            let line = disassemble(mem, addr as u16, cmos);
+            addr += line.bytes.len();
+            listing += &format!("{}\n", line);
+        }
+        listing
+    }
+
+    // The `n` instructions from PC on, for a debugger's lookahead. Reads have no side effects.
+    pub fn disassemble_next_n<T: MemIO>(&self, mem: &mut T, n: usize) -> Vec<DisasmLine> {
+        let mut lines = vec![];
+        let mut addr = self.pc;
+        for _ in 0..n {
+            let line = disassemble(mem, addr, self.cmos);
+            addr = addr.wrapping_add(line.bytes.len() as u16);
+            lines.push(line);
+        }
+        lines
+    }
+
+    // Sums the base cycles of the instructions in start..end, as if run straight
+    // through with no branches taken and no page crossed.
+    pub fn estimate_cycles<T: MemIO>(mem: &mut T, start: u16, end: u16) -> usize {
+        let mut cycles = 0;
+        let mut addr = start as usize;
+        while addr < end as usize {
+            let line = disassemble(mem, addr as u16, false);
+            if let Some(op) = OPCODES[line.bytes[0] as usize] {
+                cycles += op.base_cycles() as usize;
+            }
+            addr += line.bytes.len();
+        }
+        cycles
     }
 
     pub fn set_zero_and_negative_flag(&mut self, byte: u8) {
@@ -155,84 +828,1813 @@ impl CPU {
         self.set_zero_and_negative_flag(byte);
     }
 
-    pub fn execute<T: Reset + MemIO>(&mut self, mut cycles: isize, ram: &mut T) {
+    // A + operand + C into A, setting C, V, Z and N. Shared by ADC and RRA.
+    pub fn adc_core(&mut self, operand: u8) {
+        let (byte, overflowing1) = self.a.overflowing_add(operand);
+        let (byte, overflowing2) = byte.overflowing_add(self.flags.c as u8);
+        self.flags.c = overflowing1 || overflowing2;
+        self.flags.v = (((self.a ^ byte) & 0x80) != 0) && (((operand ^ byte) & 0x80) != 0);
+        self.set_accumulator(byte);
+    }
+
+    // A - operand - !C into A, setting C, V, Z and N. Shared by SBC and ISB.
+    pub fn sbc_core(&mut self, operand: u8) {
+        let (byte, overflowing1) = self.a.overflowing_sub(operand);
+        let (byte, overflowing2) = byte.overflowing_sub(!self.flags.c as u8);
+        self.flags.c = !(overflowing1 || overflowing2);
+        self.flags.v = (((self.a ^ operand) & 0x80) != 0) && (((self.a ^ byte) & 0x80) != 0);
+        self.set_accumulator(byte);
+    }
+
+    // Resets, then runs for `cycles` cycles including the reset sequence.
+    // Panics where `run` would return an error.
+    pub fn execute<T: Reset + MemIO>(&mut self, cycles: isize, ram: &mut T) {
         self.reset(ram);
-        cycles -= 2;
+        if let Err(error) = self.run(cycles, ram) {
+            not_implemented(error);
+        }
+    }
+
+    // Runs for `cycles` cycles from the current state, so calls can be chained.
+    // Stops early if an opcode can't run.
+    pub fn run<T: MemIO>(&mut self, mut cycles: isize, ram: &mut T) -> Result<(), StepError> {
         while cycles > 0 {
-            self.step(ram);
+            self.step(ram)?;
             cycles -= 1;
         }
+        Ok(())
+    }
+
+    // Like `step`, but panics instead of returning an error.
+    pub fn step_unchecked<T: MemIO>(&mut self, ram: &mut T) {
+        if let Err(error) = self.step(ram) {
+            not_implemented(error);
+        }
     }
 
-    pub fn step<T: MemIO>(&mut self, ram: &mut T) {
+    // Ticks one cycle. The whole instruction runs on its first cycle and the rest are
+    // waited out. Like hardware, the IRQ line is polled going into the last cycle of
+    // each instruction, so an IRQ asserted after that waits for the next instruction.
+    // An opcode that can't run is dealt with as `unknown_opcode_policy` says.
+    pub fn step<T: MemIO>(&mut self, ram: &mut T) -> Result<(), StepError> {
+        if self.halted && !self.is_waiting_for_cycles() {
+            // the clock keeps running while the CPU is stuck
+            self.count_cycles(1);
+            return Ok(());
+        }
+        let mut result = Ok(());
         if !self.is_waiting_for_cycles() {
-            let op = self.fetch_byte(ram) as usize;
-            if let Some(op) = &OPCODES[op] {
-                if cfg!(feature = "logging") {
-                    println!("{}", self.log(op, ram));
-                }
-                op.execute(self, ram);
-                self.total_cycles += self.remain_cycles;
+            if self.nmi_pending {
+                self.nmi_pending = false;
+                self.enter_interrupt(ram, Interrupt::NMI);
+            } else if self.irq_pending && !self.flags.i {
+                self.irq_pending = false;
+                self.enter_interrupt(ram, Interrupt::IRQ);
             } else {
-                panic!("{:#01X} is not implemented!", op);
+                // I may have been set since the poll, e.g. by the host
+                self.irq_pending = false;
+                result = self.fetch_and_execute(ram);
             }
+        } else if self.remain_cycles == 1 {
+            self.irq_pending = self.irq_line && !self.flags.i;
         }
         self.remain_cycles -= 1;
+        result
     }
 
-    fn is_waiting_for_cycles(&self) -> bool {
-        self.remain_cycles > 0
+    fn fetch_and_execute<T: MemIO>(&mut self, ram: &mut T) -> Result<(), StepError> {
+        if self.capture_bus {
+            let mut recorder = BusRecorder {
+                mem: ram,
+                cycles: vec![],
+            };
+            let result = self.decode_and_execute(&mut recorder);
+            self.bus_cycles = recorder.cycles;
+            result
+        } else {
+            self.decode_and_execute(ram)
+        }
     }
 
-    #[cfg(not(feature = "logging"))]
-    fn log<T: MemIO>(&mut self, _op: &OpCode, _ram: &mut T) -> String {
-        "".to_string()
+    fn decode_and_execute<T: MemIO>(&mut self, ram: &mut T) -> Result<(), StepError> {
+        self.instruction_pc = self.pc;
+        let op = self.fetch_byte(ram);
+        if self.recent_trace_len > 0 {
+            if self.recent_trace.len() == self.recent_trace_len {
+                self.recent_trace.pop_front();
+            }
+            self.recent_trace.push_back((self.instruction_pc, op));
+        }
+        match &self.runnable_opcode(op) {
+            Some(op) => {
+                if self.detect_self_modifying {
+                    self.record_instruction(self.pc.wrapping_sub(1), op);
+                }
+                if cfg!(any(feature = "logging", feature = "log")) {
+                    if let Some(line) = self.trace_line(op, ram) {
+                        emit_trace(&line);
+                    }
+                }
+                op.execute(self, ram);
+                if self.strict_b_flag {
+                    assert!(!self.flags.b, "{:?} left the B flag set", op);
+                }
+                self.count_cycles(self.remain_cycles);
+                self.total_instructions += 1;
+                Ok(())
+            }
+            _ => {
+                if let Some(mut handler) = self.hooks.illegal_handler.take() {
+                    handler(self, ram, op);
+                    self.hooks.illegal_handler.get_or_insert(handler);
+                    if self.strict_b_flag {
+                        assert!(!self.flags.b, "the illegal handler left the B flag set");
+                    }
+                    self.count_cycles(self.remain_cycles);
+                    self.total_instructions += 1;
+                    return Ok(());
+                }
+                match self.unknown_opcode_policy {
+                    UnknownOpcodePolicy::Panic => panic!("{:#01X} is not implemented!", op),
+                    UnknownOpcodePolicy::Nop => {
+                        self.dummy_read(ram, self.pc);
+                    }
+                    UnknownOpcodePolicy::Halt => {
+                        self.halted = true;
+                        self.pc = self.instruction_pc;
+                    }
+                    UnknownOpcodePolicy::Error => {
+                        self.pc = self.instruction_pc;
+                        return Err(StepError::UnknownOpcode(op));
+                    }
+                }
+                self.count_cycles(self.remain_cycles);
+                self.total_instructions += 1;
+                Ok(())
+            }
+        }
     }
 
-    #[cfg(feature = "logging")]
-    fn log<T: MemIO>(&mut self, op: &OpCode, ram: &mut T) -> String {
-        format!(
-            "{:04X}  {} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X}",
-            self.pc - 1,
-            op.log(self, ram),
-            self.a,
-            self.x,
-            self.y,
-            self.flags.get_as_u8(),
-            self.sp
-        )
+    fn count_cycles(&mut self, cycles: usize) {
+        self.total_cycles += cycles;
+        if let Some(callback) = &mut self.hooks.cycle_callback {
+            callback(cycles);
+        }
     }
-}
 
-impl StatusFlag {
-    pub fn get_as_u8(&mut self) -> u8 {
-        let byte = self.c as u8
-            + ((self.z as u8) << 1)
-            + ((self.i as u8) << 2)
-            + ((self.d as u8) << 3)
-            + ((self.b as u8) << 4)
-            + ((self.r as u8) << 5)
-            + ((self.v as u8) << 6)
-            + ((self.n as u8) << 7);
-        byte
+    // Lets the host advance its own devices by the cycles each instruction takes.
+    pub fn set_cycle_callback(&mut self, callback: Box<dyn FnMut(usize)>) {
+        self.hooks.cycle_callback = Some(callback);
     }
 
-    pub fn set_as_u8(&mut self, byte: u8) {
-        self.c = (byte >> 0 & 1) == 1;
-        self.z = (byte >> 1 & 1) == 1;
+    // Runs `handler` in place of opcodes that can't run: undefined ones, and unofficial
+    // ones while `allow_unofficial` is off. It gets the opcode byte with PC just past it,
+    // and the cycle of the opcode fetch already counted.
+    pub fn set_illegal_handler(&mut self, handler: Box<IllegalFn>) {
+        self.hooks.illegal_handler = Some(handler);
+    }
+
+    // Takes over the state of `state`, keeping this CPU's hooks.
+    pub(crate) fn restore(&mut self, mut state: CPU) {
+        state.hooks = std::mem::take(&mut self.hooks);
+        *self = state;
+    }
+
+    // Drives the (level-triggered) IRQ line.
+    pub fn set_irq_line(&mut self, asserted: bool) {
+        self.irq_line = asserted;
+    }
+
+    pub fn assert_irq(&mut self) {
+        self.set_irq_line(true);
+    }
+
+    pub fn release_irq(&mut self) {
+        self.set_irq_line(false);
+    }
+
+    // NMI is edge-triggered: one call is one NMI, taken at the next instruction boundary.
+    pub fn trigger_nmi(&mut self) {
+        self.nmi_pending = true;
+    }
+
+    // The opcode `byte` runs as, or None if it's left to the illegal handler or
+    // `unknown_opcode_policy`.
+    fn runnable_opcode(&self, byte: u8) -> Option<OpCode> {
+        decode(byte, self.cmos).filter(|op| self.allow_unofficial || op.is_official())
+    }
+
+    // Runs a whole instruction and reports what it did. A byte that can't run as an
+    // opcode is dealt with as by `step`, and reported with only the byte itself.
+    pub fn step_detailed<T: MemIO>(&mut self, ram: &mut T) -> Result<StepResult, StepError> {
+        while self.is_waiting_for_cycles() {
+            self.step(ram)?;
+        }
+        let pc_before = self.pc;
+        let opcode = self.runnable_opcode(ram.read_byte_without_effect(pc_before as usize));
+        let len = opcode.map_or(0, |op| op.1.operand_len() as u16);
+        let bytes = (0..=len)
+            .map(|i| ram.read_byte_without_effect(pc_before.wrapping_add(i) as usize))
+            .collect();
+        let cycles = self.try_step_instruction(ram)?;
+        Ok(StepResult {
+            pc_before,
+            opcode,
+            bytes,
+            cycles,
+            regs_after: self.registers(),
+        })
+    }
+
+    // Runs `count` instructions and records each one, without the `logging` feature.
+    pub fn run_traced<T: MemIO>(
+        &mut self,
+        count: usize,
+        ram: &mut T,
+    ) -> Result<Vec<TraceEntry>, StepError> {
+        (0..count)
+            .map(|_| {
+                let text = disassemble(ram, self.pc, self.cmos).text;
+                let result = self.step_detailed(ram)?;
+                Ok(TraceEntry {
+                    pc: result.pc_before,
+                    opcode: result.opcode,
+                    text,
+                    registers: result.regs_after,
+                    total_cycles: self.total_cycles,
+                })
+            })
+            .collect()
+    }
+
+    // Runs about one frame worth of cycles, finishing the last instruction even if
+    // it overruns. The overrun is carried over and taken out of the next frame.
+    pub fn run_frame<T: MemIO>(
+        &mut self,
+        cycles_per_frame: usize,
+        ram: &mut T,
+    ) -> Result<usize, StepError> {
+        let budget = cycles_per_frame.saturating_sub(self.cycle_debt);
+        let mut cycles = 0;
+        while cycles < budget {
+            cycles += self.try_step_instruction(ram)?;
+        }
+        self.cycle_debt = (self.cycle_debt + cycles).saturating_sub(cycles_per_frame);
+        Ok(cycles)
+    }
+
+    // Runs until the wall clock passes `deadline`, for guest code that may never stop.
+    pub fn run_with_deadline<T: MemIO>(&mut self, ram: &mut T, deadline: Instant) -> StopReason {
+        loop {
+            for _ in 0..DEADLINE_CHECK_INTERVAL {
+                if let Err(error) = self.try_step_instruction(ram) {
+                    return StopReason::Error(error);
+                }
+            }
+            if Instant::now() >= deadline {
+                return StopReason::Timeout;
+            }
+        }
+    }
+
+    // Registers a watch for `run_with_watches` and returns its index.
+    pub fn add_watch(&mut self, watch: WatchExpr) -> usize {
+        self.hooks.watches.push(watch);
+        self.hooks.watches.len() - 1
+    }
+
+    // Makes `run_with_watches` stop after any instruction that writes to `addr`.
+    pub fn watch_zp(&mut self, addr: u8) {
+        if !self.zp_watches.contains(&addr) {
+            self.zp_watches.push(addr);
+        }
+    }
+
+    // Runs instructions until a watch holds after one of them, a zero-page byte
+    // passed to `watch_zp` is written, or the CPU halts.
+    pub fn run_with_watches<T: MemIO>(&mut self, ram: &mut T) -> StopReason {
+        self.zp_write = None;
+        loop {
+            if let Err(error) = self.try_step_instruction(ram) {
+                return StopReason::Error(error);
+            }
+            if let Some((addr, pc)) = self.zp_write.take() {
+                return StopReason::ZeroPageWrite { addr, pc };
+            }
+            let mut watches = std::mem::take(&mut self.hooks.watches);
+            let fired = watches.iter_mut().position(|watch| (watch.0)(self, ram));
+            self.hooks.watches = watches;
+            if let Some(index) = fired {
+                return StopReason::WatchExpr(index);
+            }
+            if self.halted {
+                return StopReason::Halted;
+            }
+        }
+    }
+
+    // Steps until the current instruction is done and returns the cycles it took.
+    // Panics where `try_step_instruction` would return an error.
+    pub fn step_instruction<T: MemIO>(&mut self, ram: &mut T) -> usize {
+        self.try_step_instruction(ram)
+            .unwrap_or_else(|error| not_implemented(error))
+    }
+
+    // Like `step_instruction`, but returns the error of a `step` that fails.
+    pub fn try_step_instruction<T: MemIO>(&mut self, ram: &mut T) -> Result<usize, StepError> {
+        let mut cycles = 0;
+        loop {
+            self.step(ram)?;
+            cycles += 1;
+            if !self.is_waiting_for_cycles() {
+                return Ok(cycles);
+            }
+        }
+    }
+
+    // Calls the subroutine at `addr` as if by JSR and runs until its RTS comes back,
+    // returning the registers at that point. Gives `StepError::Timeout` if that takes
+    // over `max_cycles`.
+    pub fn call_subroutine<T: MemIO>(
+        &mut self,
+        ram: &mut T,
+        addr: u16,
+        max_cycles: usize,
+    ) -> Result<Registers, StepError> {
+        let return_addr = self.pc;
+        let sp = self.sp;
+        self.push_return_address(ram, return_addr);
+        self.set_pc(addr);
+        let mut cycles = 0;
+        while cycles < max_cycles {
+            cycles += self.try_step_instruction(ram)?;
+            if self.pc == return_addr && self.sp == sp {
+                return Ok(self.registers());
+            }
+        }
+        Err(StepError::Timeout(max_cycles))
+    }
+
+    // Runs until `flag` has `value` at an instruction boundary, or about `max_cycles` pass.
+    pub fn run_until_flag<T: MemIO>(
+        &mut self,
+        flag: Flag,
+        value: bool,
+        ram: &mut T,
+        max_cycles: usize,
+    ) -> StopReason {
+        let mut cycles = 0;
+        loop {
+            if self.flags.get(flag) == value {
+                return StopReason::Flag(flag);
+            }
+            if cycles >= max_cycles {
+                return StopReason::Timeout;
+            }
+            match self.try_step_instruction(ram) {
+                Ok(taken) => cycles += taken,
+                Err(error) => return StopReason::Error(error),
+            }
+        }
+    }
+
+    // Limits tracing to instructions whose address is in `range`. `None` traces everything.
+    pub fn set_trace_range(&mut self, range: Option<Range<u16>>) {
+        self.trace_range = range.map(|range| (range.start, range.end));
+    }
+
+    // Called right after the opcode fetch, so the instruction starts at PC - 1.
+    fn trace_line<T: MemIO>(&mut self, op: &OpCode, ram: &mut T) -> Option<String> {
+        let pc = self.pc.wrapping_sub(1);
+        match self.trace_range {
+            Some((start, end)) if !(start..end).contains(&pc) => None,
+            _ => Some(self.log(op, ram)),
+        }
+    }
+
+    fn is_waiting_for_cycles(&self) -> bool {
+        self.remain_cycles > 0
+    }
+
+    #[cfg(not(any(feature = "logging", feature = "log")))]
+    fn log<T: MemIO>(&mut self, _op: &OpCode, _ram: &mut T) -> String {
+        "".to_string()
+    }
+
+    #[cfg(any(feature = "logging", feature = "log"))]
+    fn log<T: MemIO>(&mut self, op: &OpCode, ram: &mut T) -> String {
+        format!(
+            "{:04X}  {} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X}",
+            self.pc.wrapping_sub(1),
+            op.log(self, ram),
+            self.a,
+            self.x,
+            self.y,
+            self.flags.get_as_u8(),
+            self.sp
+        )
+    }
+}
+
+// How the entry points that don't return a `StepError` give up on one
+fn not_implemented(error: StepError) -> ! {
+    match error {
+        StepError::UnknownOpcode(op) => panic!("{:#01X} is not implemented!", op),
+        StepError::Timeout(_) => panic!("{}", error),
+    }
+}
+
+// `logging` prints trace lines to stdout, `log` sends them to the `log` crate at trace level.
+#[allow(unused_variables)]
+fn emit_trace(line: &str) {
+    #[cfg(feature = "logging")]
+    println!("{}", line);
+    #[cfg(feature = "log")]
+    log::trace!("{}", line);
+}
+
+// Bit 5 isn't backed by a flip-flop: it reads as 1 in every pushed or queried status
+// byte, and writes to it are ignored, so `r` never affects the value.
+impl StatusFlag {
+    pub fn get(&self, flag: Flag) -> bool {
+        match flag {
+            Flag::C => self.c,
+            Flag::Z => self.z,
+            Flag::I => self.i,
+            Flag::D => self.d,
+            Flag::B => self.b,
+            Flag::V => self.v,
+            Flag::N => self.n,
+        }
+    }
+
+    pub fn set(&mut self, flag: Flag, value: bool) {
+        let field = match flag {
+            Flag::C => &mut self.c,
+            Flag::Z => &mut self.z,
+            Flag::I => &mut self.i,
+            Flag::D => &mut self.d,
+            Flag::B => &mut self.b,
+            Flag::V => &mut self.v,
+            Flag::N => &mut self.n,
+        };
+        *field = value;
+    }
+
+    pub fn clear(&mut self, flag: Flag) {
+        self.set(flag, false);
+    }
+
+    pub fn toggle(&mut self, flag: Flag) {
+        self.set(flag, !self.get(flag));
+    }
+
+    pub fn get_as_u8(&mut self) -> u8 {
+        self.c as u8
+            + ((self.z as u8) << 1)
+            + ((self.i as u8) << 2)
+            + ((self.d as u8) << 3)
+            + ((self.b as u8) << 4)
+            + (1 << 5)
+            + ((self.v as u8) << 6)
+            + ((self.n as u8) << 7)
+    }
+
+    pub fn set_as_u8(&mut self, byte: u8) {
+        self.c = (byte & 1) == 1;
+        self.z = (byte >> 1 & 1) == 1;
         self.i = (byte >> 2 & 1) == 1;
         self.d = (byte >> 3 & 1) == 1;
         self.b = (byte >> 4 & 1) == 1;
-        // self.r = (byte >> 5 & 1) == 1;
-        self.r = true; // always true ?
+        self.r = true;
         self.v = (byte >> 6 & 1) == 1;
         self.n = (byte >> 7 & 1) == 1;
     }
 }
 
+#[cfg(test)]
+mod test_cpu {
+    use super::*;
+    use crate::instruction::OPCODES;
+    use crate::ram::RAM;
+
+    #[test]
+    fn test_run_frame() {
+        let mut cpu = CPU::default();
+        let mut ram = RAM::default();
+        ram.write_rom(
+            0x8000,
+            &[
+                0xEA, //             NOP
+                0xEA, //             NOP
+                0x4C, 0x00, 0x80, // JMP $8000
+            ],
+        );
+        ram[0xFFFC] = 0x00;
+        ram[0xFFFD] = 0x80;
+        cpu.reset(&mut ram);
+        cpu.remain_cycles = 0;
+
+        let cycles_per_frame = 100;
+        let mut total = 0;
+        for frame in 1..=5 {
+            total += cpu.run_frame(cycles_per_frame, &mut ram).unwrap();
+            let expected = frame * cycles_per_frame;
+            assert!(total >= expected);
+            assert!(total - expected < 7);
+            assert_eq!(cpu.cycle_debt, total - expected);
+        }
+        assert_eq!(cpu.remain_cycles, 0);
+    }
+
+    #[test]
+    fn test_effective_address() {
+        let mut cpu = CPU::default();
+        let mut ram = RAM::default();
+        ram.write_rom(
+            0x8000,
+            &[
+                0xB5, 0x10, // LDA $10,X
+                0xA9, 0x10, // LDA #$10
+            ],
+        );
+
+        cpu.pc = 0x8000;
+        cpu.x = 0x05;
+        assert_eq!(cpu.effective_address(&mut ram), Some(0x0015));
+        assert_eq!(cpu.pc, 0x8000);
+        assert_eq!(cpu.remain_cycles, 0);
+
+        cpu.pc = 0x8002;
+        assert_eq!(cpu.effective_address(&mut ram), None);
+    }
+
+    // Trace lines sent to the `log` crate. The logger can only be set once per process,
+    // so every test that looks at trace output shares this one.
+    #[cfg(feature = "log")]
+    fn captured_trace() -> &'static std::sync::Mutex<Vec<String>> {
+        use std::sync::{Mutex, Once};
+
+        struct Capture(Mutex<Vec<String>>);
+
+        impl log::Log for Capture {
+            fn enabled(&self, metadata: &log::Metadata) -> bool {
+                metadata.level() <= log::Level::Trace
+            }
+
+            fn log(&self, record: &log::Record) {
+                self.0.lock().unwrap().push(format!("{}", record.args()));
+            }
+
+            fn flush(&self) {}
+        }
+
+        static LOGGER: Capture = Capture(Mutex::new(Vec::new()));
+        static INIT: Once = Once::new();
+        INIT.call_once(|| {
+            log::set_logger(&LOGGER).unwrap();
+            log::set_max_level(log::LevelFilter::Trace);
+        });
+        &LOGGER.0
+    }
+
+    #[cfg(feature = "log")]
+    #[test]
+    fn test_trace_range() {
+        let mut cpu = CPU::default();
+        let mut ram = RAM::default();
+        ram.write_rom(
+            0x5A00,
+            &[
+                0xA9, 0x01, // LDA #$01
+                0xA2, 0x02, // LDX #$02
+                0xA0, 0x03, // LDY #$03
+            ],
+        );
+        cpu.set_trace_range(Some(0x5A02..0x5A04));
+        cpu.pc = 0x5A00;
+        let trace = captured_trace();
+        for _ in 0..3 {
+            cpu.step_instruction(&mut ram);
+        }
+
+        // other tests may be logging at the same time
+        let lines = trace.lock().unwrap();
+        let ours: Vec<&String> = lines
+            .iter()
+            .filter(|line| line.starts_with("5A0"))
+            .collect();
+        assert_eq!(ours.len(), 1);
+        assert!(ours[0].starts_with("5A02  A2 02"));
+    }
+
+    #[cfg(feature = "log")]
+    #[test]
+    fn test_log_backend() {
+        let mut cpu = CPU::default();
+        let mut ram = RAM::default();
+        ram.write_rom(0x8000, &[0xA9, 0x42]); // LDA #$42
+        cpu.pc = 0x8000;
+        let trace = captured_trace();
+        cpu.step_instruction(&mut ram);
+
+        // other tests may be logging at the same time
+        let lines = trace.lock().unwrap();
+        assert!(lines.iter().any(|line| line.starts_with("8000  A9 42")));
+    }
+
+    #[test]
+    fn test_self_modifying_code() {
+        let mut cpu = CPU::default();
+        let mut ram = RAM::default();
+        ram.write_rom(
+            0x8000,
+            &[
+                0xA9, 0x42, //       LDA #$42
+                0x8D, 0x00, 0x02, // STA $0200
+                0x8D, 0x01, 0x80, // STA $8001; patches the operand of LDA
+            ],
+        );
+        cpu.pc = 0x8000;
+        cpu.detect_self_modifying = true;
+
+        cpu.step_detailed(&mut ram).unwrap();
+        cpu.step_detailed(&mut ram).unwrap();
+        assert_eq!(cpu.take_warning(), None);
+        cpu.step_detailed(&mut ram).unwrap();
+        assert_eq!(
+            cpu.take_warning(),
+            Some(CpuWarning::SelfModifyingCode { addr: 0x8001 })
+        );
+        assert_eq!(cpu.take_warning(), None);
+    }
+
+    #[test]
+    fn test_interrupt_cycles() {
+        let mut cpu = CPU::default();
+        let mut ram = RAM::default();
+        ram[0xFFFE] = 0x34;
+        ram[0xFFFF] = 0x12;
+        cpu.pc = 0x8000;
+        cpu.sp = 0xFF;
+
+        cpu.interrupt(&mut ram, Interrupt::IRQ);
+        assert_eq!(cpu.pc, 0x1234);
+        assert_eq!(cpu.remain_cycles, 7);
+        assert_eq!(cpu.total_cycles, 7);
+
+        for _ in 0..7 {
+            cpu.step_unchecked(&mut ram);
+        }
+        assert_eq!(cpu.pc, 0x1234);
+        assert_eq!(cpu.remain_cycles, 0);
+
+        // masked
+        cpu.interrupt(&mut ram, Interrupt::IRQ);
+        assert_eq!(cpu.remain_cycles, 0);
+        assert_eq!(cpu.total_cycles, 7);
+    }
+
+    #[test]
+    fn test_irq_poll() {
+        let mut cpu = CPU::default();
+        let mut ram = RAM::default();
+        ram.write_rom(0x8000, &[0xEA, 0xEA, 0xEA]); // NOP x3
+        ram.write_rom(0x9000, &[0x40]); // RTI
+        ram.write_rom(0xFFFE, &[0x00, 0x90]);
+        cpu.pc = 0x8000;
+        cpu.sp = 0xFF;
+
+        // asserted during the first NOP, before its last cycle
+        cpu.step_unchecked(&mut ram);
+        cpu.set_irq_line(true);
+        cpu.step_unchecked(&mut ram);
+        cpu.set_irq_line(false);
+        assert_eq!(cpu.step_instruction(&mut ram), 7);
+        assert_eq!(cpu.pc, 0x9000);
+        assert_eq!((ram[0x01FF], ram[0x01FE]), (0x80, 0x01));
+        cpu.step_instruction(&mut ram); // RTI
+
+        // asserted only after the second NOP is over, so the third one still runs
+        cpu.step_unchecked(&mut ram);
+        cpu.step_unchecked(&mut ram);
+        cpu.set_irq_line(true);
+        assert_eq!(cpu.step_instruction(&mut ram), 2);
+        assert_eq!(cpu.pc, 0x8003);
+        assert_eq!(cpu.step_instruction(&mut ram), 7);
+        assert_eq!(cpu.pc, 0x9000);
+        assert_eq!((ram[0x01FF], ram[0x01FE]), (0x80, 0x03));
+    }
+
+    #[test]
+    fn test_irq_poll_masked_since() {
+        let mut cpu = CPU::default();
+        let mut ram = RAM::default();
+        ram.write_rom(0x8000, &[0xEA, 0xEA]); // NOP x2
+        cpu.pc = 0x8000;
+        cpu.sp = 0xFF;
+
+        cpu.step_unchecked(&mut ram);
+        cpu.set_irq_line(true);
+        cpu.step_unchecked(&mut ram);
+        // the IRQ was polled, then the host masks it before it's taken
+        cpu.flags.i = true;
+        assert_eq!(cpu.step_instruction(&mut ram), 2);
+        assert_eq!(cpu.pc, 0x8002);
+        assert_eq!(cpu.sp, 0xFF);
+    }
+
+    #[test]
+    fn test_disassemble_program() {
+        let mut ram = RAM::default();
+        ram.write_rom(
+            0x8000,
+            &[
+                0xA2, 0x02, //       LDX #$02
+                0xB5, 0x40, //       LDA $40,X
+                0x8D, 0x00, 0x02, // STA $0200
+                0x60, //             RTS
+            ],
+        );
+
+        let listing = CPU::disassemble_program(&mut ram, 0x8000, 0x8008, false);
+        let lines: Vec<&str> = listing.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0], "8000  A2 02     LDX #$02");
+        assert_eq!(lines[2], "8004  8D 00 02  STA $0200");
+        assert_eq!(lines[3], "8007  60        RTS");
+    }
+
+    #[test]
+    fn test_estimate_cycles() {
+        let mut cpu = CPU::default();
+        let mut ram = RAM::default();
+        let program = [
+            0xA2, 0x05, //       LDX #$05
+            0xA9, 0x10, //       LDA #$10
+            0x85, 0x20, //       STA $20
+            0xE8, //             INX
+            0x9D, 0x00, 0x03, // STA $0300,X
+            0xA8, //             TAY
+            0x48, //             PHA
+            0x18, //             CLC
+            0x69, 0x01, //       ADC #$01
+        ];
+        ram.write_rom(0x8000, &program);
+        let end = 0x8000 + program.len() as u16;
+
+        let estimate = CPU::estimate_cycles(&mut ram, 0x8000, end);
+        cpu.pc = 0x8000;
+        cpu.sp = 0xFF;
+        let mut cycles = 0;
+        while cpu.pc < end {
+            cycles += cpu.step_instruction(&mut ram);
+        }
+        assert_eq!(estimate, 23);
+        assert_eq!(estimate, cycles);
+    }
+
+    #[test]
+    fn test_diff() {
+        let mut cpu = CPU::default();
+        let mut ram = RAM::default();
+        ram.write_rom(0x8000, &[0xA9, 0x42]); // LDA #$42
+        cpu.pc = 0x8000;
+
+        let before = cpu.clone();
+        assert_eq!(cpu, before);
+        assert_eq!(cpu.diff(&before), Vec::<String>::new());
+
+        cpu.step_instruction(&mut ram);
+        let mut expected = before;
+        expected.pc = 0x8002;
+        expected.total_cycles = 2;
+        assert_ne!(cpu, expected);
+        assert_eq!(expected.diff(&cpu), vec!["a: 00 != 42"]);
+    }
+
+    #[test]
+    fn test_diff_matches_eq() {
+        let cpu = CPU::default();
+        let mut other = cpu.clone();
+        other.halted = true;
+        assert_eq!(cpu, other);
+        assert_eq!(cpu.diff(&other), Vec::<String>::new());
+
+        other.total_cycles = 1;
+        assert_ne!(cpu, other);
+        assert_eq!(cpu.diff(&other), vec!["total_cycles: 0 != 1"]);
+    }
+
+    #[test]
+    fn test_disassemble_next_n() {
+        let mut cpu = CPU::default();
+        let mut ram = RAM::default();
+        ram.write_rom(
+            0x8000,
+            &[
+                0xA9, 0x42, //       LDA #$42
+                0xAB, //             undefined
+                0x8D, 0x00, 0x02, // STA $0200
+                0xEA, //             NOP
+            ],
+        );
+        cpu.pc = 0x8000;
+
+        let lines = cpu.disassemble_next_n(&mut ram, 3);
+        let lines: Vec<_> = lines
+            .iter()
+            .map(|line| (line.addr, &line.text[..]))
+            .collect();
+        assert_eq!(
+            lines,
+            vec![
+                (0x8000, "LDA #$42"),
+                (0x8002, ".byte $AB"),
+                (0x8003, "STA $0200")
+            ]
+        );
+        assert_eq!(cpu.pc, 0x8000);
+    }
+
+    #[test]
+    fn test_capture_bus() {
+        let mut cpu = CPU::default();
+        let mut ram = RAM::default();
+        ram.write_rom(0x8000, &[0xA5, 0x10]); // LDA $10
+        ram[0x10] = 0x42;
+        cpu.pc = 0x8000;
+        cpu.capture_bus = true;
+
+        assert_eq!(cpu.step_instruction(&mut ram), 3);
+        let cycle = |addr, value| BusCycle {
+            addr,
+            value,
+            is_write: false,
+        };
+        assert_eq!(
+            cpu.bus_cycles(),
+            &[
+                cycle(0x8000, 0xA5),
+                cycle(0x8001, 0x10),
+                cycle(0x0010, 0x42)
+            ]
+        );
+    }
+
+    // Every cycle is a bus access, including the internal ones
+    #[test]
+    fn test_capture_bus_every_cycle() {
+        let mut cpu = CPU::default();
+        let mut ram = RAM::default();
+        ram.write_rom(0x8000, &[0xE8, 0xE6, 0x10]); // INX; INC $10
+        ram[0x10] = 0x41;
+        cpu.pc = 0x8000;
+        cpu.capture_bus = true;
+        let cycle = |addr, value, is_write| BusCycle {
+            addr,
+            value,
+            is_write,
+        };
+
+        assert_eq!(cpu.step_instruction(&mut ram), 2);
+        assert_eq!(
+            cpu.bus_cycles(),
+            &[cycle(0x8000, 0xE8, false), cycle(0x8001, 0xE6, false)]
+        );
+        assert_eq!(cpu.step_instruction(&mut ram), 5);
+        assert_eq!(
+            cpu.bus_cycles(),
+            &[
+                cycle(0x8001, 0xE6, false),
+                cycle(0x8002, 0x10, false),
+                cycle(0x0010, 0x41, false),
+                cycle(0x0010, 0x41, true),
+                cycle(0x0010, 0x42, true)
+            ]
+        );
+
+        for (byte, op) in OPCODES.iter().enumerate() {
+            if op.is_none() {
+                continue;
+            }
+            let mut cpu = CPU::default();
+            let mut ram = RAM::default();
+            ram.write_rom(0x8000, &[byte as u8, 0xF0, 0x80]);
+            cpu.pc = 0x8000;
+            cpu.x = 0x20;
+            cpu.y = 0x20;
+            cpu.capture_bus = true;
+            let cycles = cpu.step_instruction(&mut ram);
+            assert_eq!(cpu.bus_cycles().len(), cycles, "{:#04X}", byte);
+        }
+    }
+
+    #[test]
+    fn test_dump_state() {
+        let mut cpu = CPU::default();
+        let mut ram = RAM::default();
+        cpu.pc = 0x8000;
+        cpu.a = 0x42;
+        cpu.sp = 0xFD;
+        ram[0x01FE] = 0x12;
+        ram[0x01FF] = 0x34;
+        ram[0x11] = 0xAB;
+
+        let dump = cpu.dump_state(&mut ram);
+        assert!(dump.contains("PC:8000 A:42 X:00 Y:00 P:20 SP:FD\n"));
+        assert!(dump.contains("Stack: 12 34\n"));
+        assert!(dump.contains("Zero page:\n0000  00 00"));
+        assert!(dump.contains("0010  00 AB 00"));
+        assert_eq!(dump.lines().count(), 19);
+    }
+
+    #[test]
+    fn test_clone_state_into() {
+        let mut cpu = CPU::default();
+        let mut ram = RAM::default();
+        ram.write_rom(0x8000, &[0xA9, 0x42, 0x38]); // LDA #$42; SEC
+        cpu.pc = 0x8000;
+        cpu.step_instruction(&mut ram);
+        cpu.step_instruction(&mut ram);
+
+        let mut other = CPU::default();
+        other.total_cycles = 100;
+        other.allow_unofficial = false;
+        cpu.clone_state_into(&mut other);
+        assert_eq!(other.registers(), cpu.registers());
+        assert_eq!(other.total_cycles, 100);
+        assert_eq!(other.allow_unofficial, false);
+    }
+
+    #[test]
+    fn test_run_with_deadline() {
+        let mut cpu = CPU::default();
+        let mut ram = RAM::default();
+        ram.write_rom(0x8000, &[0x4C, 0x00, 0x80]); // JMP $8000
+        cpu.pc = 0x8000;
+
+        let deadline = Instant::now() + std::time::Duration::from_millis(10);
+        assert_eq!(
+            cpu.run_with_deadline(&mut ram, deadline),
+            StopReason::Timeout
+        );
+        assert!(Instant::now() >= deadline);
+        assert_eq!(cpu.pc, 0x8000);
+    }
+
+    #[test]
+    fn test_cycle_callback() {
+        let mut cpu = CPU::default();
+        let mut ram = RAM::default();
+        ram.write_rom(
+            0x8000,
+            &[
+                0xA9, 0x42, //       LDA #$42
+                0x8D, 0x00, 0x02, // STA $0200
+                0x48, //             PHA
+                0x4C, 0x00, 0x80, // JMP $8000
+            ],
+        );
+        cpu.pc = 0x8000;
+        cpu.sp = 0xFF;
+
+        let reported = std::rc::Rc::new(std::cell::Cell::new(0));
+        let sum = reported.clone();
+        cpu.set_cycle_callback(Box::new(move |cycles| sum.set(sum.get() + cycles)));
+        for _ in 0..10 {
+            cpu.step_instruction(&mut ram);
+        }
+        cpu.interrupt(&mut ram, Interrupt::NMI);
+        assert_eq!(reported.get(), cpu.total_cycles);
+        assert_eq!(cpu.total_cycles, 2 * (2 + 4 + 3 + 3) + 2 + 4 + 7);
+    }
+
+    #[test]
+    fn test_adc_core() {
+        // (a, operand, carry in) => (a, c, v)
+        let cases = [
+            ((0x01, 0x01, false), (0x02, false, false)),
+            ((0x01, 0x01, true), (0x03, false, false)),
+            ((0x7F, 0x01, false), (0x80, false, true)),
+            ((0x80, 0xFF, false), (0x7F, true, true)),
+            ((0xFF, 0x01, false), (0x00, true, false)),
+            ((0xFF, 0x00, true), (0x00, true, false)),
+            ((0x50, 0x50, false), (0xA0, false, true)),
+            ((0xD0, 0x90, false), (0x60, true, true)),
+        ];
+        for &((a, operand, carry), (result, c, v)) in cases.iter() {
+            let mut cpu = CPU::default();
+            cpu.a = a;
+            cpu.flags.c = carry;
+            cpu.adc_core(operand);
+            assert_eq!((cpu.a, cpu.flags.c, cpu.flags.v), (result, c, v));
+            assert_eq!(cpu.flags.z, result == 0);
+            assert_eq!(cpu.flags.n, result >= 0x80);
+
+            // SBC is ADC of the complement
+            let mut sbc = CPU::default();
+            sbc.a = a;
+            sbc.flags.c = carry;
+            sbc.sbc_core(!operand);
+            assert_eq!(sbc.registers(), cpu.registers());
+        }
+    }
+
+    #[test]
+    fn test_peek_poke_word() {
+        let cpu = CPU::default();
+        let mut ram = RAM::default();
+
+        cpu.poke_word(&mut ram, 0x0200, 0x1234);
+        assert_eq!((ram[0x0200], ram[0x0201]), (0x34, 0x12));
+        assert_eq!(cpu.peek_word(&mut ram, 0x0200), 0x1234);
+
+        cpu.poke_word(&mut ram, 0x02FF, 0xBEEF);
+        assert_eq!((ram[0x02FF], ram[0x0300]), (0xEF, 0xBE));
+        assert_eq!(cpu.peek_word(&mut ram, 0x02FF), 0xBEEF);
+        assert_eq!(cpu.remain_cycles, 0);
+    }
+
+    #[test]
+    fn test_run_with_watches() {
+        let mut cpu = CPU::default();
+        let mut ram = RAM::default();
+        ram.write_rom(
+            0x8000,
+            &[
+                0x69, 0x01, //       ADC #$01
+                0x85, 0x10, //       STA $10
+                0x4C, 0x00, 0x80, // JMP $8000
+            ],
+        );
+        cpu.pc = 0x8000;
+
+        cpu.add_watch(WatchExpr::new(|_, mem| {
+            mem.read_byte_without_effect(0x10) > 5
+        }));
+        let target = cpu.add_watch(WatchExpr::new(|cpu, _| cpu.a == 0x03));
+        assert_eq!(
+            cpu.run_with_watches(&mut ram),
+            StopReason::WatchExpr(target)
+        );
+        assert_eq!(cpu.pc, 0x8002);
+        assert_eq!(ram[0x10], 0x02);
+
+        cpu.a = 0x05;
+        assert_eq!(cpu.run_with_watches(&mut ram), StopReason::WatchExpr(0));
+        assert_eq!(ram[0x10], 0x06);
+    }
+
+    #[test]
+    fn test_watch_zp() {
+        let mut cpu = CPU::default();
+        let mut ram = RAM::default();
+        ram.write_rom(
+            0x8000,
+            &[
+                0xA9, 0x01, // LDA #$01
+                0x85, 0x04, // STA $04
+                0x85, 0x05, // STA $05
+                0xE6, 0x04, // INC $04
+            ],
+        );
+        cpu.pc = 0x8000;
+        cpu.watch_zp(0x05);
+
+        assert_eq!(
+            cpu.run_with_watches(&mut ram),
+            StopReason::ZeroPageWrite {
+                addr: 0x05,
+                pc: 0x8004
+            }
+        );
+        assert_eq!(cpu.pc, 0x8006);
+    }
+
+    #[test]
+    fn test_run_until_flag() {
+        let mut cpu = CPU::default();
+        let mut ram = RAM::default();
+        ram.write_rom(
+            0x8000,
+            &[
+                0x18, //       CLC
+                0x69, 0x40, // ADC #$40
+                0x90, 0xFC, // BCC $8001
+            ],
+        );
+        cpu.pc = 0x8000;
+
+        assert_eq!(
+            cpu.run_until_flag(Flag::C, true, &mut ram, 1000),
+            StopReason::Flag(Flag::C)
+        );
+        assert_eq!(cpu.pc, 0x8003);
+        assert_eq!(cpu.a, 0x00);
+
+        assert_eq!(
+            cpu.run_until_flag(Flag::V, true, &mut ram, 10),
+            StopReason::Timeout
+        );
+    }
+
+    #[test]
+    fn test_run_traced() {
+        let mut cpu = CPU::default();
+        let mut ram = RAM::default();
+        ram.write_rom(
+            0x8000,
+            &[
+                0xA2, 0x02, //       LDX #$02
+                0xCA, //             DEX
+                0x8E, 0x00, 0x02, // STX $0200
+            ],
+        );
+        cpu.pc = 0x8000;
+
+        let trace = cpu.run_traced(3, &mut ram).unwrap();
+        let texts: Vec<&str> = trace.iter().map(|entry| entry.text.as_str()).collect();
+        assert_eq!(texts, vec!["LDX #$02", "DEX", "STX $0200"]);
+        assert_eq!(trace[1].pc, 0x8002);
+        assert_eq!(trace[1].opcode, OPCODES[0xCA]);
+        assert_eq!(trace[1].registers.x, 0x01);
+        assert_eq!(trace[2].total_cycles, 8);
+    }
+
+    #[test]
+    fn test_disassemble_cmos() {
+        let mut cpu = CPU::default();
+        let mut ram = RAM::default();
+        ram.write_rom(
+            0x8000,
+            &[
+                0x12, 0x20, // ORA ($20)
+                0x04, 0x10, // TSB $10
+                0xA9, 0x01, // LDA #$01
+            ],
+        );
+        cpu.pc = 0x8000;
+        cpu.cmos = true;
+
+        let lines = cpu.disassemble_next_n(&mut ram, 3);
+        let lines: Vec<_> = lines.iter().map(|line| &line.text[..]).collect();
+        assert_eq!(lines, vec!["ORA ($20)", "TSB $10", "LDA #$01"]);
+        let listing = CPU::disassemble_program(&mut ram, 0x8000, 0x8006, true);
+        assert_eq!(listing.lines().nth(1), Some("8002  04 10     TSB $10"));
+
+        let trace = cpu.run_traced(3, &mut ram).unwrap();
+        let texts: Vec<&str> = trace.iter().map(|entry| entry.text.as_str()).collect();
+        assert_eq!(texts, vec!["ORA ($20)", "TSB $10", "LDA #$01"]);
+        assert_eq!(trace[0].opcode, decode(0x12, true));
+    }
+
+    #[test]
+    fn test_cmos_indirect_zero_page() {
+        let mut cpu = CPU::default();
+        let mut ram = RAM::default();
+        ram.write_rom(0x8000, &[0xB2, 0x20]); // LDA ($20)
+        ram.write_rom(0x0020, &[0x34, 0x12]);
+        ram[0x1234] = 0x42;
+        cpu.pc = 0x8000;
+        cpu.cmos = true;
+
+        assert_eq!(cpu.effective_address(&mut ram), Some(0x1234));
+        assert_eq!(cpu.step_instruction(&mut ram), 5);
+        assert_eq!(cpu.a, 0x42);
+
+        // the pointer wraps within the zero page
+        ram.write_rom(0x8002, &[0xB2, 0xFF]); // LDA ($FF)
+        ram[0x00FF] = 0x78;
+        ram[0x0000] = 0x56;
+        ram[0x5678] = 0x84;
+        cpu.step_instruction(&mut ram);
+        assert_eq!(cpu.a, 0x84);
+    }
+
+    #[test]
+    fn test_cmos_trb_tsb() {
+        let mut cpu = CPU::default();
+        let mut ram = RAM::default();
+        ram.write_rom(
+            0x8000,
+            &[
+                0x04, 0x10, //       TSB $10
+                0x1C, 0x00, 0x02, // TRB $0200
+                0x14, 0x10, //       TRB $10
+            ],
+        );
+        ram[0x10] = 0x0C;
+        ram[0x0200] = 0xF0;
+        cpu.pc = 0x8000;
+        cpu.a = 0x0A;
+        cpu.cmos = true;
+
+        assert_eq!(cpu.step_instruction(&mut ram), 5);
+        assert_eq!(ram[0x10], 0x0E);
+        assert_eq!(cpu.flags.z, false);
+
+        assert_eq!(cpu.step_instruction(&mut ram), 6);
+        assert_eq!(ram[0x0200], 0xF0);
+        assert_eq!(cpu.flags.z, true);
+
+        cpu.step_instruction(&mut ram);
+        assert_eq!(ram[0x10], 0x04);
+        assert_eq!(cpu.flags.z, false);
+        assert_eq!(cpu.a, 0x0A);
+
+        // NMOS reads the same bytes as IGN
+        let mut cpu = CPU::default();
+        cpu.pc = 0x8000;
+        cpu.a = 0x0A;
+        ram[0x10] = 0x0C;
+        cpu.step_instruction(&mut ram);
+        assert_eq!(ram[0x10], 0x0C);
+    }
+
+    #[test]
+    fn test_total_instructions() {
+        let mut cpu = CPU::default();
+        let mut ram = RAM::default();
+        ram.write_rom(
+            0x8000,
+            &[
+                0xA2, 0x03, // LDX #$03
+                0xCA, //       DEX
+                0xD0, 0xFD, // BNE -3
+                0xEA, //       NOP
+            ],
+        );
+        ram.write_rom(0xFFFC, &[0x00, 0x80]);
+
+        cpu.reset(&mut ram);
+        while cpu.pc != 0x8006 {
+            cpu.step_unchecked(&mut ram);
+        }
+        assert_eq!(cpu.total_instructions(), 1 + 3 * 2 + 1);
+
+        cpu.reset(&mut ram);
+        assert_eq!(cpu.total_instructions(), 0);
+    }
+
+    #[test]
+    fn test_irq_handler() {
+        let mut cpu = CPU::default();
+        let mut ram = RAM::default();
+        ram.write_rom(0x8000, &[0xEA, 0xEA]); // NOP x2
+        ram.write_rom(0x9000, &[0xE8, 0x40]); // INX, RTI
+        ram.set_irq_vector(0x9000);
+        cpu.pc = 0x8000;
+        cpu.sp = 0xFF;
+
+        cpu.assert_irq();
+        cpu.step_instruction(&mut ram);
+        assert_eq!(cpu.step_instruction(&mut ram), 7);
+        assert_eq!(cpu.pc, 0x9000);
+        assert_eq!(cpu.flags.i, true);
+        cpu.release_irq();
+
+        cpu.step_instruction(&mut ram); // INX
+        cpu.step_instruction(&mut ram); // RTI
+        assert_eq!(cpu.x, 0x01);
+        assert_eq!(cpu.pc, 0x8001);
+        assert_eq!(cpu.sp, 0xFF);
+        assert_eq!(cpu.flags.i, false);
+    }
+
+    #[test]
+    fn test_trigger_nmi() {
+        let mut cpu = CPU::default();
+        let mut ram = RAM::default();
+        ram.write_rom(0x8000, &[0xEA]); // NOP
+        ram.set_nmi_vector(0x9000);
+        cpu.pc = 0x8000;
+        cpu.sp = 0xFF;
+        cpu.flags.i = true;
+
+        cpu.trigger_nmi();
+        assert_eq!(cpu.step_instruction(&mut ram), 7);
+        assert_eq!(cpu.pc, 0x9000);
+        assert_eq!(cpu.total_cycles, 7);
+    }
+
+    #[test]
+    fn test_relocated_vector_across_page() {
+        let mut cpu = CPU::default();
+        let mut ram = RAM::default();
+        cpu.vector_base = 0x12FF;
+        ram.write_rom(0x12FF, &[0x34, 0x12]); // NMI vector straddling $12FF/$1300
+        ram[0x1200] = 0x56;
+        cpu.pc = 0x8000;
+        cpu.sp = 0xFF;
+
+        cpu.interrupt(&mut ram, Interrupt::NMI);
+        assert_eq!(cpu.pc, 0x1234);
+    }
+
+    #[test]
+    fn test_reset() {
+        let mut cpu = CPU::default();
+        let mut ram = RAM::default();
+        ram[0xFFFC] = 0x34;
+        ram[0xFFFD] = 0x12;
+        cpu.pc = 0x8000;
+
+        cpu.reset(&mut ram);
+        assert_eq!(cpu.pc, 0x1234);
+        assert_eq!(cpu.remain_cycles, RESET_CYCLES);
+        assert_eq!(cpu.total_cycles, RESET_CYCLES);
+        for _ in 0..RESET_CYCLES {
+            cpu.step_unchecked(&mut ram);
+        }
+        assert_eq!(cpu.pc, 0x1234);
+    }
+
+    #[test]
+    fn test_reset_cycle_counters() {
+        let mut cpu = CPU::default();
+        let mut ram = RAM::default();
+        ram.write_rom(0x8000, &[0xAD, 0x00, 0x02]); // LDA $0200
+        ram.write_rom(0xFFFC, &[0x00, 0x80]);
+
+        cpu.execute(10, &mut ram);
+        assert_eq!(cpu.total_cycles, RESET_CYCLES + 4);
+        assert_ne!(cpu.remain_cycles, 0);
+
+        cpu.reset(&mut ram);
+        assert_eq!(cpu.total_cycles, RESET_CYCLES);
+        assert_eq!(cpu.remain_cycles, RESET_CYCLES);
+    }
+
+    #[test]
+    fn test_run() {
+        let mut cpu = CPU::default();
+        let mut ram = RAM::default();
+        ram.write_rom(0x8000, &[0xE8, 0xE8, 0xE8]); // INX; INX; INX
+        ram.write_rom(0xFFFC, &[0x00, 0x80]);
+
+        cpu.execute(RESET_CYCLES as isize + 2, &mut ram);
+        assert_eq!(cpu.x, 1);
+        cpu.run(4, &mut ram).unwrap();
+        assert_eq!(cpu.x, 3);
+        assert_eq!(cpu.pc, 0x8003);
+        assert_eq!(cpu.total_cycles, RESET_CYCLES + 6);
+    }
+
+    #[test]
+    fn test_power_on_and_soft_reset() {
+        let mut cpu = CPU::default();
+        let mut ram = RAM::default();
+        ram[0x10] = 0x42;
+        ram.set_reset_vector(0x8000);
+        cpu.a = 0x12;
+        cpu.sp = 0xF0;
+
+        cpu.soft_reset(&mut ram);
+        assert_eq!(cpu.pc, 0x8000);
+        assert_eq!(cpu.sp, 0xED);
+        assert_eq!(cpu.a, 0x12);
+        assert_eq!(cpu.flags.i, true);
+        assert_eq!(ram[0x10], 0x42);
+
+        ram.randomize(1);
+        ram.set_reset_vector(0x8000);
+        cpu.power_on(&mut ram);
+        assert_eq!(cpu.pc, 0x8000);
+        assert_eq!(cpu.sp, 0xFD);
+        assert_eq!(cpu.a, 0x00);
+        assert_eq!(cpu.flags.i, true);
+        assert_ne!(ram.as_slice()[..0x100], [0; 0x100][..]);
+    }
+
+    #[test]
+    fn test_reset_mid_instruction() {
+        let mut cpu = CPU::default();
+        let mut ram = RAM::default();
+        ram.write_rom(0x8000, &[0xAD, 0x00, 0x02]); // LDA $0200
+        ram.write_rom(0x9000, &[0xE8]); //             INX
+        ram.set_reset_vector(0x9000);
+        ram.set_nmi_vector(0xA000);
+        cpu.pc = 0x8000;
+
+        cpu.step_unchecked(&mut ram);
+        cpu.trigger_nmi();
+        assert_ne!(cpu.remain_cycles, 0);
+
+        cpu.reset(&mut ram);
+        assert_eq!(cpu.remain_cycles, RESET_CYCLES);
+        assert_eq!(cpu.registers().pc, 0x9000);
+        assert_eq!(cpu.a, 0);
+        cpu.step_instruction(&mut ram);
+        cpu.step_instruction(&mut ram);
+        assert_eq!(cpu.x, 1);
+        assert_eq!(cpu.pc, 0x9001);
+    }
+
+    #[test]
+    fn test_push_return_address() {
+        let mut cpu = CPU::default();
+        let mut ram = RAM::default();
+        ram[0x8000] = 0x60; // RTS
+        cpu.pc = 0x8000;
+        cpu.sp = 0xFF;
+
+        cpu.push_return_address(&mut ram, 0x1234);
+        assert_eq!(cpu.sp, 0xFD);
+        assert_eq!(cpu.remain_cycles, 0);
+        cpu.step_detailed(&mut ram).unwrap();
+        assert_eq!(cpu.pc, 0x1234);
+        assert_eq!(cpu.sp, 0xFF);
+    }
+
+    #[test]
+    fn test_stack_wraps_within_page() {
+        let mut cpu = CPU::default();
+        let mut ram = RAM::default();
+
+        cpu.sp = 0x00;
+        cpu.push_to_stack(&mut ram, 0x42);
+        assert_eq!(ram[0x0100], 0x42);
+        assert_eq!(cpu.sp, 0xFF);
+        cpu.push_to_stack(&mut ram, 0x43);
+        assert_eq!(ram[0x01FF], 0x43);
+        assert_eq!(cpu.sp, 0xFE);
+
+        assert_eq!(cpu.pull_from_stack(&mut ram), 0x43);
+        assert_eq!(cpu.sp, 0xFF);
+        assert_eq!(cpu.pull_from_stack(&mut ram), 0x42);
+        assert_eq!(cpu.sp, 0x00);
+        assert_eq!((ram[0x00FF], ram[0x0200]), (0x00, 0x00));
+    }
+
+    #[test]
+    fn test_set_pc() {
+        let mut cpu = CPU::default();
+        let mut ram = RAM::default();
+        ram.write_rom(0x8000, &[0xAD, 0x00, 0x02]); // LDA $0200
+        ram.write_rom(0x9000, &[0xA9, 0x42]); // LDA #$42
+
+        cpu.pc = 0x8000;
+        cpu.step_unchecked(&mut ram);
+        assert_ne!(cpu.remain_cycles, 0);
+
+        cpu.set_pc(0x9000);
+        assert_eq!(cpu.pc(), 0x9000);
+        assert_eq!(cpu.step_instruction(&mut ram), 2);
+        assert_eq!(cpu.a, 0x42);
+        assert_eq!(cpu.pc(), 0x9002);
+    }
+
+    #[test]
+    fn test_call_subroutine() {
+        let mut cpu = CPU::default();
+        let mut ram = RAM::default();
+        ram.write_rom(
+            0x9000,
+            &[
+                0xA9, 0x42, // LDA #$42
+                0x60, //       RTS
+            ],
+        );
+        cpu.pc = 0x8000;
+        cpu.sp = 0xFF;
+
+        let regs = cpu.call_subroutine(&mut ram, 0x9000, 100).unwrap();
+        assert_eq!(regs.a, 0x42);
+        assert_eq!(regs.pc, 0x8000);
+        assert_eq!(regs.sp, 0xFF);
+    }
+
+    #[test]
+    fn test_call_subroutine_timeout() {
+        let mut cpu = CPU::default();
+        let mut ram = RAM::default();
+        ram.write_rom(0x9000, &[0x4C, 0x00, 0x90]); // JMP $9000
+        cpu.pc = 0x8000;
+
+        let result = cpu.call_subroutine(&mut ram, 0x9000, 100);
+        assert_eq!(result, Err(StepError::Timeout(100)));
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "didn't return within 100 cycles"
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_trace_entry_json() {
+        let entry = TraceEntry {
+            pc: 0x8000,
+            opcode: OPCODES[0xA9],
+            text: "LDA #$00".to_string(),
+            registers: CPU::default().registers(),
+            total_cycles: 7,
+        };
+        let json = serde_json::to_value(&entry).unwrap();
+        assert_eq!(json["pc"], 0x8000);
+        assert_eq!(json["text"], "LDA #$00");
+        assert_eq!(json["opcode"][0], "LDA");
+        assert_eq!(json["opcode"][1], "Immediate");
+        assert_eq!(json["registers"]["pc"], 0);
+        assert_eq!(json["registers"]["flags"]["r"], true);
+        assert_eq!(json["total_cycles"], 7);
+    }
+
+    #[test]
+    fn test_allow_unofficial() {
+        let mut cpu = CPU::default();
+        let mut ram = RAM::default();
+        ram.write_rom(0x8000, &[0xA7, 0x10]); // LAX $10
+        ram[0x10] = 0x42;
+        cpu.pc = 0x8000;
+
+        assert_eq!(cpu.allow_unofficial, true);
+        cpu.step_instruction(&mut ram);
+        assert_eq!((cpu.a, cpu.x), (0x42, 0x42));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_disallow_unofficial() {
+        let mut cpu = CPU::default();
+        let mut ram = RAM::default();
+        ram.write_rom(0x8000, &[0xA7, 0x10]); // LAX $10
+        cpu.pc = 0x8000;
+        cpu.allow_unofficial = false;
+        cpu.step_instruction(&mut ram);
+    }
+
+    #[test]
+    fn test_recent_trace() {
+        let mut cpu = CPU::default();
+        let mut ram = RAM::default();
+        ram.write_rom(
+            0x8000,
+            &[
+                0xA9, 0x01, // LDA #$01
+                0xA2, 0x02, // LDX #$02
+                0xE8, //       INX
+                0xC8, //       INY
+                0x02, //       JAM
+            ],
+        );
+        cpu.pc = 0x8000;
+        cpu.set_recent_trace_len(3);
+
+        for _ in 0..5 {
+            cpu.step_instruction(&mut ram);
+        }
+        assert_eq!(
+            cpu.recent_trace(),
+            vec![(0x8004, 0xE8), (0x8005, 0xC8), (0x8006, 0x02)]
+        );
+
+        cpu.set_recent_trace_len(0);
+        assert_eq!(cpu.recent_trace(), vec![]);
+    }
+
+    #[test]
+    fn test_step_error() {
+        let mut cpu = CPU::default();
+        let mut ram = RAM::default();
+        ram.write_rom(0x8000, &[0xE8, 0xAB]); // INX; undefined
+        cpu.pc = 0x8000;
+        cpu.unknown_opcode_policy = UnknownOpcodePolicy::Error;
+
+        assert_eq!(cpu.step(&mut ram), Ok(()));
+        assert_eq!(cpu.step(&mut ram), Ok(()));
+        assert_eq!(cpu.step(&mut ram), Err(StepError::UnknownOpcode(0xAB)));
+        assert_eq!(cpu.pc, 0x8001);
+        assert_eq!(cpu.remain_cycles, 0);
+
+        // the instruction-level entry points pass the error on instead of panicking
+        let error = StepError::UnknownOpcode(0xAB);
+        assert_eq!(cpu.try_step_instruction(&mut ram), Err(error));
+        assert_eq!(cpu.run(10, &mut ram), Err(error));
+        assert_eq!(cpu.run_frame(100, &mut ram), Err(error));
+        assert_eq!(cpu.call_subroutine(&mut ram, 0x8001, 100), Err(error));
+        let stop = StopReason::Error(error);
+        assert_eq!(cpu.run_with_watches(&mut ram), stop);
+        assert_eq!(cpu.run_until_flag(Flag::C, true, &mut ram, 100), stop);
+        let deadline = Instant::now() + std::time::Duration::from_secs(1);
+        assert_eq!(cpu.run_with_deadline(&mut ram, deadline), stop);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_unknown_opcode_panics() {
+        let mut cpu = CPU::default();
+        let mut ram = RAM::default();
+        ram[0x8000] = 0xAB; // undefined
+        cpu.pc = 0x8000;
+        let _ = cpu.step(&mut ram);
+    }
+
+    #[test]
+    fn test_unknown_opcode_policy() {
+        let mut cpu = CPU::default();
+        let mut ram = RAM::default();
+        ram.write_rom(0x8000, &[0xAB, 0xE8]); // undefined; INX
+
+        cpu.pc = 0x8000;
+        cpu.unknown_opcode_policy = UnknownOpcodePolicy::Nop;
+        assert_eq!(cpu.step_instruction(&mut ram), 2);
+        assert_eq!(cpu.pc, 0x8001);
+        cpu.step_instruction(&mut ram);
+        assert_eq!(cpu.x, 1);
+
+        cpu.pc = 0x8000;
+        cpu.unknown_opcode_policy = UnknownOpcodePolicy::Halt;
+        cpu.step_instruction(&mut ram);
+        assert_eq!(cpu.halted, true);
+        cpu.step_instruction(&mut ram);
+        assert_eq!(cpu.pc, 0x8000);
+        assert_eq!(cpu.x, 1);
+        cpu.halted = false;
+
+        cpu.unknown_opcode_policy = UnknownOpcodePolicy::Error;
+        assert_eq!(cpu.step(&mut ram), Err(StepError::UnknownOpcode(0xAB)));
+        assert_eq!(cpu.pc, 0x8000);
+    }
+
+    #[test]
+    fn test_illegal_handler() {
+        let mut cpu = CPU::default();
+        let mut ram = RAM::default();
+        ram.write_rom(
+            0x8000,
+            &[
+                0xAB, 0x07, //       host call 7
+                0x8D, 0x00, 0x02, // STA $0200
+            ],
+        );
+        cpu.pc = 0x8000;
+        // a host call: the byte after the illegal opcode selects the service
+        cpu.set_illegal_handler(Box::new(|cpu, mem, op| {
+            assert_eq!(op, 0xAB);
+            let service = cpu.fetch_byte(mem);
+            cpu.a = service * 6;
+        }));
+
+        assert_eq!(cpu.step_instruction(&mut ram), 2);
+        assert_eq!(cpu.a, 42);
+        cpu.step_instruction(&mut ram);
+        assert_eq!(ram[0x0200], 42);
+    }
+
+    #[test]
+    fn test_brk_returns_past_padding() {
+        let mut cpu = CPU::default();
+        let mut ram = RAM::default();
+        ram.write_rom(0x8000, &[0x00, 0xFF, 0xE8]); // BRK; padding; INX
+        ram.write_rom(0x9000, &[0x40]); // RTI
+        ram.set_irq_vector(0x9000);
+        cpu.pc = 0x8000;
+        cpu.sp = 0xFF;
+
+        assert_eq!(cpu.step_instruction(&mut ram), 7);
+        assert_eq!(cpu.peek_word(&mut ram, 0x01FE), 0x8002);
+        assert_eq!(cpu.step_instruction(&mut ram), 6);
+        assert_eq!(cpu.total_cycles, 13);
+        assert_eq!(cpu.pc, 0x8002);
+        cpu.step_instruction(&mut ram);
+        assert_eq!(cpu.x, 1);
+    }
+
+    #[test]
+    fn test_jam() {
+        let mut cpu = CPU::default();
+        let mut ram = RAM::default();
+        ram.write_rom(0x8000, &[0xE8, 0x02, 0xE8]); // INX; JAM; INX
+        ram.set_reset_vector(0x8000);
+        cpu.pc = 0x8000;
+
+        cpu.step_instruction(&mut ram);
+        assert_eq!(cpu.step_instruction(&mut ram), 2);
+        assert_eq!(cpu.halted, true);
+        assert_eq!(cpu.pc, 0x8001);
+
+        let cycles = cpu.total_cycles;
+        for _ in 0..10 {
+            cpu.step_unchecked(&mut ram);
+        }
+        cpu.trigger_nmi();
+        cpu.step_instruction(&mut ram);
+        assert_eq!(cpu.pc, 0x8001);
+        assert_eq!(cpu.x, 1);
+        assert_eq!(cpu.total_cycles, cycles + 11);
+        assert_eq!(cpu.run_with_watches(&mut ram), StopReason::Halted);
+
+        cpu.reset(&mut ram);
+        assert_eq!(cpu.halted, false);
+        assert_eq!(cpu.pc, 0x8000);
+    }
+
+    #[test]
+    fn test_strict_b_flag() {
+        let mut cpu = CPU::default();
+        let mut ram = RAM::default();
+        ram.write_rom(
+            0x8000,
+            &[
+                0x08, //       PHP
+                0x28, //       PLP
+                0xA9, 0x42, // LDA #$42
+            ],
+        );
+        cpu.pc = 0x8000;
+        cpu.strict_b_flag = true;
+        for _ in 0..3 {
+            cpu.step_detailed(&mut ram).unwrap();
+        }
+        assert_eq!(cpu.a, 0x42);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_strict_b_flag_violation() {
+        let mut cpu = CPU::default();
+        let mut ram = RAM::default();
+        ram[0x8000] = 0xEA; // NOP
+        cpu.pc = 0x8000;
+        cpu.strict_b_flag = true;
+        cpu.flags.b = true;
+        cpu.step_detailed(&mut ram).unwrap();
+    }
+
+    // Handlers stand in for instructions, so they're held to the same rule
+    #[test]
+    #[should_panic(expected = "the illegal handler left the B flag set")]
+    fn test_strict_b_flag_illegal_handler() {
+        let mut cpu = CPU::default();
+        let mut ram = RAM::default();
+        ram[0x8000] = 0xAB;
+        cpu.pc = 0x8000;
+        cpu.strict_b_flag = true;
+        cpu.set_illegal_handler(Box::new(|cpu, _, _| cpu.flags.set_as_u8(0b00110000)));
+        cpu.step_unchecked(&mut ram);
+    }
+
+    #[test]
+    fn test_step_detailed() {
+        let mut cpu = CPU::default();
+        let mut ram = RAM::default();
+        ram.write_rom(0x8000, &[0xA2, 0x02]); // LDX #$02
+
+        cpu.pc = 0x8000;
+        let result = cpu.step_detailed(&mut ram).unwrap();
+        assert_eq!(result.pc_before, 0x8000);
+        assert_eq!(result.opcode, OPCODES[0xA2]);
+        assert_eq!(result.bytes, vec![0xA2, 0x02]);
+        assert_eq!(result.cycles, 2);
+        assert_eq!(result.regs_after, cpu.registers());
+        assert_eq!(result.regs_after.pc, 0x8002);
+        assert_eq!(result.regs_after.x, 0x02);
+
+        // bytes that can't run go through the same policy and handler as `step`
+        ram[0x8002] = 0xAB;
+        cpu.unknown_opcode_policy = UnknownOpcodePolicy::Error;
+        assert_eq!(
+            cpu.step_detailed(&mut ram),
+            Err(StepError::UnknownOpcode(0xAB))
+        );
+        assert_eq!(
+            cpu.run_traced(1, &mut ram),
+            Err(StepError::UnknownOpcode(0xAB))
+        );
+        cpu.unknown_opcode_policy = UnknownOpcodePolicy::Nop;
+        let result = cpu.step_detailed(&mut ram).unwrap();
+        assert_eq!(result.opcode, None);
+        assert_eq!(result.bytes, vec![0xAB]);
+        assert_eq!(result.cycles, 2);
+        assert_eq!(result.regs_after.pc, 0x8003);
+    }
+}
+
+#[cfg(test)]
+mod test_words {
+    use super::*;
+
+    #[test]
+    fn test_lo_hi() {
+        assert_eq!(lo(0x1234), 0x34);
+        assert_eq!(hi(0x1234), 0x12);
+        assert_eq!(lo(0x00FF), 0xFF);
+        assert_eq!(hi(0xFF00), 0xFF);
+    }
+
+    #[test]
+    fn test_make_word() {
+        assert_eq!(make_word(0x34, 0x12), 0x1234);
+        assert_eq!(make_word(0xFF, 0x00), 0x00FF);
+        assert_eq!(make_word(lo(0xBEEF), hi(0xBEEF)), 0xBEEF);
+    }
+}
+
 #[cfg(test)]
 mod test_status_flags {
     use super::*;
+    use crate::ram::RAM;
 
     #[test]
     fn test_get_as_u8() {
@@ -246,7 +2648,23 @@ mod test_status_flags {
             v: true,
             n: false,
         };
-        assert_eq!(sf.get_as_u8(), 0b01010101);
+        assert_eq!(sf.get_as_u8(), 0b01110101);
+    }
+
+    #[test]
+    fn test_reserved_bit() {
+        let mut sf = StatusFlag::default();
+        sf.r = false;
+        assert_eq!(sf.get_as_u8(), 0b00100000);
+        sf.set_as_u8(0b00000000);
+        assert_eq!(sf.r, true);
+
+        let mut cpu = CPU::default();
+        let mut ram = RAM::default();
+        cpu.sp = 0xFF;
+        cpu.flags.r = false;
+        cpu.interrupt(&mut ram, Interrupt::NMI);
+        assert_eq!(ram[0x01FD] & 0b00100000, 0b00100000);
     }
 
     #[test]
@@ -267,4 +2685,34 @@ mod test_status_flags {
             }
         );
     }
+
+    #[test]
+    fn test_flag_enum() {
+        let flags = [
+            (Flag::C, 0),
+            (Flag::Z, 1),
+            (Flag::I, 2),
+            (Flag::D, 3),
+            (Flag::B, 4),
+            (Flag::V, 6),
+            (Flag::N, 7),
+        ];
+        for &(flag, bit) in flags.iter() {
+            let mut sf = StatusFlag::default();
+            assert_eq!(sf.get(flag), false);
+            sf.set(flag, true);
+            assert_eq!(sf.get(flag), true);
+            assert_eq!(sf.get_as_u8(), 0b00100000 | 1 << bit);
+            sf.set(flag, false);
+            assert_eq!(sf, StatusFlag::default());
+
+            sf.toggle(flag);
+            assert_eq!(sf.get(flag), true);
+            sf.toggle(flag);
+            assert_eq!(sf.get(flag), false);
+            sf.set(flag, true);
+            sf.clear(flag);
+            assert_eq!(sf, StatusFlag::default());
+        }
+    }
 }
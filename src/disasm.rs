@@ -0,0 +1,117 @@
+use crate::cpu::make_word;
+use crate::instruction::{decode, AddressingMode};
+use crate::ram::MemIO;
+
+// One disassembled instruction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DisasmLine {
+    pub addr: u16,
+    pub bytes: Vec<u8>,
+    pub text: String, // e.g. "LDA $10,X"
+}
+
+impl std::fmt::Display for DisasmLine {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let bytes = self
+            .bytes
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect::<Vec<_>>()
+            .join(" ");
+        write!(f, "{:04X}  {: <8}  {}", self.addr, bytes, self.text)
+    }
+}
+
+// Decodes the instruction at `addr` using side-effect-free reads, with the 65C02
+// opcodes when `cmos` is set. Undefined opcodes come out as a single `.byte`.
+pub fn disassemble<T: MemIO>(mem: &mut T, addr: u16, cmos: bool) -> DisasmLine {
+    let ins_byte = mem.read_byte_without_effect(addr as usize);
+    let op = match decode(ins_byte, cmos) {
+        Some(op) => op,
+        None => {
+            return DisasmLine {
+                addr,
+                bytes: vec![ins_byte],
+                text: format!(".byte ${:02X}", ins_byte),
+            }
+        }
+    };
+
+    let mut bytes = vec![ins_byte];
+    for i in 1..=op.1.operand_len() as u16 {
+        bytes.push(mem.read_byte_without_effect(addr.wrapping_add(i) as usize));
+    }
+    let operand = match op.1 {
+        AddressingMode::Implied => "".to_string(),
+        AddressingMode::Accumulator => "A".to_string(),
+        AddressingMode::Immediate => format!("#${:02X}", bytes[1]),
+        AddressingMode::ZeroPage => format!("${:02X}", bytes[1]),
+        AddressingMode::ZeroPageX => format!("${:02X},X", bytes[1]),
+        AddressingMode::ZeroPageY => format!("${:02X},Y", bytes[1]),
+        AddressingMode::Relative => format!(
+            "${:04X}",
+            addr.wrapping_add(2).wrapping_add(bytes[1] as i8 as u16)
+        ),
+        AddressingMode::Absolute => format!("${:04X}", make_word(bytes[1], bytes[2])),
+        AddressingMode::AbsoluteX => format!("${:04X},X", make_word(bytes[1], bytes[2])),
+        AddressingMode::AbsoluteY => format!("${:04X},Y", make_word(bytes[1], bytes[2])),
+        AddressingMode::Indirect => format!("(${:04X})", make_word(bytes[1], bytes[2])),
+        AddressingMode::IndexedIndirect => format!("(${:02X},X)", bytes[1]),
+        AddressingMode::IndirectIndexed => format!("(${:02X}),Y", bytes[1]),
+        AddressingMode::IndirectZeroPage => format!("(${:02X})", bytes[1]),
+    };
+    let text = if operand.is_empty() {
+        op.0.mnemonic()
+    } else {
+        format!("{} {}", op.0.mnemonic(), operand)
+    };
+
+    DisasmLine { addr, bytes, text }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ram::RAM;
+
+    #[test]
+    fn test_disassemble() {
+        let mut ram = RAM::default();
+        ram.write_rom(
+            0x8000,
+            &[
+                0xB5, 0x10, //       LDA $10,X
+                0x6C, 0x34, 0x12, // JMP ($1234)
+                0xD0, 0xF9, //       BNE $8000
+                0xAB, //             undefined
+            ],
+        );
+
+        let line = disassemble(&mut ram, 0x8000, false);
+        assert_eq!(line.bytes, vec![0xB5, 0x10]);
+        assert_eq!(line.text, "LDA $10,X");
+        assert_eq!(disassemble(&mut ram, 0x8002, false).text, "JMP ($1234)");
+        assert_eq!(disassemble(&mut ram, 0x8005, false).text, "BNE $8000");
+        assert_eq!(disassemble(&mut ram, 0x8007, false).text, ".byte $AB");
+    }
+
+    #[test]
+    fn test_disassemble_cmos() {
+        let mut ram = RAM::default();
+        ram.write_rom(
+            0x8000,
+            &[
+                0x12, 0x20, // ORA ($20)
+                0x04, 0x10, // TSB $10
+            ],
+        );
+
+        let line = disassemble(&mut ram, 0x8000, true);
+        assert_eq!(line.bytes, vec![0x12, 0x20]);
+        assert_eq!(line.text, "ORA ($20)");
+        assert_eq!(disassemble(&mut ram, 0x8002, true).text, "TSB $10");
+        // The same bytes on the NMOS part
+        assert_eq!(disassemble(&mut ram, 0x8000, false).text, "JAM");
+        assert_eq!(disassemble(&mut ram, 0x8002, false).text, "NOP $10");
+    }
+}
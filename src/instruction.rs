@@ -1,9 +1,8 @@
-use std::usize;
-
-use crate::cpu::{Interrupt, CPU};
+use crate::cpu::{hi, lo, make_word, Interrupt, CPU};
 use crate::ram::MemIO;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Instruction {
     LDA,
     LDX,
@@ -73,6 +72,10 @@ pub enum Instruction {
     NOP,
     RTI,
     //
+    // 65C02
+    TRB,
+    TSB,
+    //
     // Unofficial
     // see also https://wiki.nesdev.com/w/index.php/Programming_with_unofficial_opcodes
     // Convined operations
@@ -85,12 +88,44 @@ pub enum Instruction {
     RRA,
     SLO,
     SRE,
+    ANC,
+    ALR,
+    ARR,
+    ANE,
+    SBX,
+    LAS,
+    // Unstable stores
+    SHA,
+    SHX,
+    SHY,
+    TAS,
+    // Halts
+    JAM,
     // NOPs
     SKB,
     IGN,
 }
 
+impl Instruction {
+    // assembler name; the unofficial NOP variants all read as NOP
+    pub fn mnemonic(&self) -> String {
+        match self {
+            SKB | IGN => "NOP".to_string(),
+            _ => format!("{:?}", self),
+        }
+    }
+
+    // whether the instruction may change PC other than by stepping over itself
+    pub fn is_control_flow(&self) -> bool {
+        matches!(
+            self,
+            JMP | JSR | RTS | RTI | BRK | BCC | BCS | BNE | BEQ | BPL | BMI | BVC | BVS | JAM
+        )
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum AddressingMode {
     Implied,
     Accumulator,
@@ -105,10 +140,12 @@ pub enum AddressingMode {
     Indirect,
     IndexedIndirect,
     IndirectIndexed,
+    IndirectZeroPage, // 65C02 only
 }
 
 // has official instruction or not
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Officiality {
     Official,
     Unofficial,
@@ -128,6 +165,81 @@ impl std::fmt::Display for Officiality {
 }
 
 impl AddressingMode {
+    // operand shape in assembler syntax, e.g. "(zp),Y"
+    pub fn schematic(&self) -> &'static str {
+        match self {
+            Implied => "",
+            Accumulator => "A",
+            Immediate => "#imm",
+            ZeroPage => "zp",
+            ZeroPageX => "zp,X",
+            ZeroPageY => "zp,Y",
+            Relative => "rel",
+            Absolute => "abs",
+            AbsoluteX => "abs,X",
+            AbsoluteY => "abs,Y",
+            Indirect => "(abs)",
+            IndexedIndirect => "(zp,X)",
+            IndirectIndexed => "(zp),Y",
+            IndirectZeroPage => "(zp)",
+        }
+    }
+
+    // number of operand bytes following the opcode
+    pub fn operand_len(&self) -> usize {
+        match self {
+            Implied => 0,
+            Accumulator => 0,
+            Immediate => 1,
+            ZeroPage => 1,
+            ZeroPageX => 1,
+            ZeroPageY => 1,
+            Relative => 1,
+            Absolute => 2,
+            AbsoluteX => 2,
+            AbsoluteY => 2,
+            Indirect => 2,
+            IndexedIndirect => 1,
+            IndirectIndexed => 1,
+            IndirectZeroPage => 1,
+        }
+    }
+
+    pub fn is_indexed(&self) -> bool {
+        matches!(
+            self,
+            ZeroPageX | ZeroPageY | AbsoluteX | AbsoluteY | IndexedIndirect | IndirectIndexed
+        )
+    }
+
+    // Whether indexing the instruction at PC will cross a page, using side-effect-free reads.
+    pub fn predict_page_cross<T: MemIO>(&self, cpu: &CPU, mem: &mut T) -> bool {
+        self.crosses_page(cpu, mem, cpu.pc.wrapping_add(1))
+    }
+
+    // `operand` is the address of the first operand byte.
+    fn crosses_page<T: MemIO>(&self, cpu: &CPU, mem: &mut T, operand: u16) -> bool {
+        let base = match self {
+            AbsoluteX | AbsoluteY => make_word(
+                mem.read_byte_without_effect(operand as usize),
+                mem.read_byte_without_effect(operand.wrapping_add(1) as usize),
+            ),
+            IndirectIndexed => {
+                let ind_addr = mem.read_byte_without_effect(operand as usize);
+                make_word(
+                    mem.read_byte_without_effect(ind_addr as usize),
+                    mem.read_byte_without_effect(ind_addr.wrapping_add(1) as usize),
+                )
+            }
+            _ => return false,
+        };
+        let addr = self.peek_address(cpu, mem, operand).unwrap();
+        hi(base) != hi(addr)
+    }
+
+    // Both `fetch` and `get_address` consume the operand bytes, advancing PC and counting
+    // their cycles, so an instruction calls exactly one of them, once. `fetch` also reads
+    // the value at the address; instructions that write use `get_address`.
     fn fetch<T: MemIO>(&self, cpu: &mut CPU, ram: &mut T) -> Option<u8> {
         match self {
             Accumulator => Some(cpu.a),
@@ -149,19 +261,11 @@ impl AddressingMode {
                 Some(cpu.read_byte(ram, addr as usize))
             }
             AbsoluteX => {
-                let before_pc = cpu.pc;
                 let addr = self.get_address(cpu, ram).unwrap();
-                if before_pc & 0xFF00 != addr & 0xFF00 {
-                    cpu.remain_cycles += 1;
-                }
                 Some(cpu.read_byte(ram, addr as usize))
             }
             AbsoluteY => {
-                let before_pc = cpu.pc;
                 let addr = self.get_address(cpu, ram).unwrap();
-                if before_pc & 0xFF00 != addr & 0xFF00 {
-                    cpu.remain_cycles += 1;
-                }
                 Some(cpu.read_byte(ram, addr as usize))
             }
             IndexedIndirect => {
@@ -169,13 +273,11 @@ impl AddressingMode {
                 Some(cpu.read_byte(ram, addr as usize))
             }
             IndirectIndexed => {
-                let ind_addr = cpu.fetch_byte(ram);
-                let addr = (cpu.read_byte(ram, ind_addr as usize) as u16
-                    + ((cpu.read_byte(ram, (ind_addr.wrapping_add(1)) as usize) as u16) << 8))
-                    .wrapping_add(cpu.y as u16);
-                if addr.wrapping_sub(cpu.y as u16) & 0xFF00 != addr & 0xFF00 {
-                    cpu.remain_cycles += 1;
-                }
+                let addr = self.get_address(cpu, ram).unwrap();
+                Some(cpu.read_byte(ram, addr as usize))
+            }
+            IndirectZeroPage => {
+                let addr = self.get_address(cpu, ram).unwrap();
                 Some(cpu.read_byte(ram, addr as usize))
             }
             Implied | Relative | Indirect => panic!("You can't call fetch from {:?}!", self),
@@ -186,33 +288,37 @@ impl AddressingMode {
         match self {
             ZeroPage => Some(cpu.fetch_byte(ram).into()),
             ZeroPageX => {
-                cpu.remain_cycles += 1; // may be consumed by add x
-                Some((cpu.fetch_byte(ram).wrapping_add(cpu.x)).into())
+                let base = cpu.fetch_byte(ram);
+                cpu.dummy_read(ram, base as u16); // adding the index
+                Some(base.wrapping_add(cpu.x).into())
             }
             ZeroPageY => {
-                cpu.remain_cycles += 1; // may be consumed by add y
-                Some((cpu.fetch_byte(ram).wrapping_add(cpu.y)).into())
+                let base = cpu.fetch_byte(ram);
+                cpu.dummy_read(ram, base as u16); // adding the index
+                Some(base.wrapping_add(cpu.y).into())
+            }
+            Relative => {
+                let offset = cpu.fetch_byte(ram) as i8;
+                Some(cpu.pc.wrapping_add(offset as u16))
             }
-            Relative => Some((((cpu.fetch_byte(ram) as i8) as i32) + cpu.pc as i32) as u16),
             Absolute => {
-                let addr = cpu.fetch_byte(ram) as u16 + ((cpu.fetch_byte(ram) as u16) << 8);
+                let addr = make_word(cpu.fetch_byte(ram), cpu.fetch_byte(ram));
 
                 Some(addr)
             }
             AbsoluteX => {
-                let addr = (cpu.fetch_byte(ram) as u16 + ((cpu.fetch_byte(ram) as u16) << 8))
-                    .wrapping_add(cpu.x as u16);
-                Some(addr)
+                let base = make_word(cpu.fetch_byte(ram), cpu.fetch_byte(ram));
+                Some(Self::index(cpu, ram, base, cpu.x))
             }
             AbsoluteY => {
-                let addr = (cpu.fetch_byte(ram) as u16 + ((cpu.fetch_byte(ram) as u16) << 8))
-                    .wrapping_add(cpu.y as u16);
-                Some(addr)
+                let base = make_word(cpu.fetch_byte(ram), cpu.fetch_byte(ram));
+                Some(Self::index(cpu, ram, base, cpu.y))
             }
             Indirect => {
-                let ind_addr = cpu.fetch_byte(ram) as u16 + ((cpu.fetch_byte(ram) as u16) << 8);
-                let addr = cpu.read_byte(ram, ind_addr as usize) as u16
-                    + ((cpu.read_byte(
+                let ind_addr = make_word(cpu.fetch_byte(ram), cpu.fetch_byte(ram));
+                let addr = make_word(
+                    cpu.read_byte(ram, ind_addr as usize),
+                    cpu.read_byte(
                         ram,
                         // http://www.obelisk.me.uk/6502/reference.html#JMP
                         // An original 6502 has does not correctly fetch the target address if the indirect
@@ -221,22 +327,34 @@ impl AddressingMode {
                         // This is fixed in some later chips like the 65SC02 so for compatibility always ensure
                         // the indirect vector is not at the end of the page.
                         ((ind_addr & 0xFF00) + ((ind_addr as u8).wrapping_add(1)) as u16) as usize,
-                    ) as u16)
-                        << 8);
+                    ),
+                );
                 Some(addr)
             }
             IndexedIndirect => {
-                let ind_addr = cpu.fetch_byte(ram).wrapping_add(cpu.x);
-                let addr = cpu.read_byte(ram, ind_addr as usize) as u16
-                    + ((cpu.read_byte(ram, (ind_addr.wrapping_add(1)) as usize) as u16) << 8);
-                cpu.remain_cycles += 1;
+                let base = cpu.fetch_byte(ram);
+                cpu.dummy_read(ram, base as u16); // adding the index
+                let ind_addr = base.wrapping_add(cpu.x);
+                let addr = make_word(
+                    cpu.read_byte(ram, ind_addr as usize),
+                    cpu.read_byte(ram, ind_addr.wrapping_add(1) as usize),
+                );
                 Some(addr)
             }
             IndirectIndexed => {
                 let ind_addr = cpu.fetch_byte(ram);
-                let addr = (cpu.read_byte(ram, ind_addr as usize) as u16
-                    + ((cpu.read_byte(ram, (ind_addr.wrapping_add(1)) as usize) as u16) << 8))
-                    .wrapping_add(cpu.y as u16);
+                let base = make_word(
+                    cpu.read_byte(ram, ind_addr as usize),
+                    cpu.read_byte(ram, ind_addr.wrapping_add(1) as usize),
+                );
+                Some(Self::index(cpu, ram, base, cpu.y))
+            }
+            IndirectZeroPage => {
+                let ind_addr = cpu.fetch_byte(ram);
+                let addr = make_word(
+                    cpu.read_byte(ram, ind_addr as usize),
+                    cpu.read_byte(ram, ind_addr.wrapping_add(1) as usize),
+                );
                 Some(addr)
             }
             Accumulator | Implied | Immediate => {
@@ -244,13 +362,157 @@ impl AddressingMode {
             }
         }
     }
+
+    // Adds the index to a base address. While the carry into the high byte is fixed up,
+    // the address without it is read, if `index_penalty` gave the instruction that cycle.
+    fn index<T: MemIO>(cpu: &mut CPU, ram: &mut T, base: u16, index: u8) -> u16 {
+        let addr = base.wrapping_add(index as u16);
+        if std::mem::take(&mut cpu.index_fix_up) {
+            cpu.dummy_read(ram, make_word(lo(addr), hi(base)));
+        }
+        addr
+    }
+
+    // SHX and friends store `value & (H + 1)`, H being the high byte of the base address.
+    // The AND is unstable on hardware when the index crosses a page; here the rule is
+    // always that the high byte of the target address is replaced by the stored value.
+    fn store_high_and<T: MemIO>(&self, cpu: &mut CPU, ram: &mut T, value: u8) {
+        let (base, index) = match self {
+            AbsoluteX => (make_word(cpu.fetch_byte(ram), cpu.fetch_byte(ram)), cpu.x),
+            AbsoluteY => (make_word(cpu.fetch_byte(ram), cpu.fetch_byte(ram)), cpu.y),
+            IndirectIndexed => {
+                let ind_addr = cpu.fetch_byte(ram);
+                let base = make_word(
+                    cpu.read_byte(ram, ind_addr as usize),
+                    cpu.read_byte(ram, ind_addr.wrapping_add(1) as usize),
+                );
+                (base, cpu.y)
+            }
+            _ => panic!("You can't store high-byte-ANDed values from {:?}!", self),
+        };
+        let addr = Self::index(cpu, ram, base, index);
+        let byte = value & hi(base).wrapping_add(1);
+        let addr = if hi(addr) != hi(base) {
+            make_word(lo(addr), byte)
+        } else {
+            addr
+        };
+        cpu.write_byte(ram, addr as usize, byte);
+    }
+
+    // Resolves the same address as get_address, but only with side-effect-free reads
+    // and without touching the CPU. `operand` is the address of the first operand byte.
+    pub fn peek_address<T: MemIO>(&self, cpu: &CPU, mem: &mut T, operand: u16) -> Option<u16> {
+        let byte = |mem: &mut T, addr: u16| mem.read_byte_without_effect(addr as usize);
+        let word = |mem: &mut T| make_word(byte(mem, operand), byte(mem, operand.wrapping_add(1)));
+        match self {
+            ZeroPage => Some(byte(mem, operand) as u16),
+            ZeroPageX => Some(byte(mem, operand).wrapping_add(cpu.x) as u16),
+            ZeroPageY => Some(byte(mem, operand).wrapping_add(cpu.y) as u16),
+            Relative => {
+                Some(((byte(mem, operand) as i8) as i32 + operand.wrapping_add(1) as i32) as u16)
+            }
+            Absolute => Some(word(mem)),
+            AbsoluteX => Some(word(mem).wrapping_add(cpu.x as u16)),
+            AbsoluteY => Some(word(mem).wrapping_add(cpu.y as u16)),
+            Indirect => {
+                let ind_addr = word(mem);
+                // same page-boundary bug as get_address
+                let high_addr = (ind_addr & 0xFF00) + ((ind_addr as u8).wrapping_add(1)) as u16;
+                Some(make_word(byte(mem, ind_addr), byte(mem, high_addr)))
+            }
+            IndexedIndirect => {
+                let ind_addr = byte(mem, operand).wrapping_add(cpu.x);
+                Some(make_word(
+                    byte(mem, ind_addr as u16),
+                    byte(mem, ind_addr.wrapping_add(1) as u16),
+                ))
+            }
+            IndirectIndexed => {
+                let ind_addr = byte(mem, operand);
+                Some(
+                    make_word(
+                        byte(mem, ind_addr as u16),
+                        byte(mem, ind_addr.wrapping_add(1) as u16),
+                    )
+                    .wrapping_add(cpu.y as u16),
+                )
+            }
+            IndirectZeroPage => {
+                let ind_addr = byte(mem, operand);
+                Some(make_word(
+                    byte(mem, ind_addr as u16),
+                    byte(mem, ind_addr.wrapping_add(1) as u16),
+                ))
+            }
+            Accumulator | Implied | Immediate => None,
+        }
+    }
+}
+
+// how an opcode touches memory other than fetching itself and its operand
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemAccess {
+    None,
+    Read,
+    Write,
+    ReadModifyWrite,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct OpCode(pub Instruction, pub AddressingMode, Officiality);
 
+impl std::fmt::Display for OpCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.1 {
+            Implied => write!(f, "{}", self.0.mnemonic()),
+            mode => write!(f, "{} {}", self.0.mnemonic(), mode.schematic()),
+        }
+    }
+}
+
 impl OpCode {
+    pub fn is_official(&self) -> bool {
+        self.2 == Official
+    }
+
+    // Cycles taken before page-crossing and branch penalties.
+    pub fn base_cycles(&self) -> u8 {
+        OPCODE_CYCLES[self.0 as usize][self.1 as usize]
+    }
+
+    pub fn memory_access(&self) -> MemAccess {
+        if let Accumulator | Implied = self.1 {
+            return MemAccess::None;
+        }
+        match self.0 {
+            LDA | LDX | LDY | AND | EOR | ORA | BIT | ADC | SBC | CMP | CPX | CPY | LAX | ANC
+            | ALR | ARR | ANE | SBX | LAS | SKB | IGN => MemAccess::Read,
+            STA | STX | STY | SAX | SHA | SHX | SHY | TAS => MemAccess::Write,
+            INC | DEC | ASL | LSR | ROL | ROR | DCP | ISB | RLA | RRA | SLO | SRE | TRB | TSB => {
+                MemAccess::ReadModifyWrite
+            }
+            _ => MemAccess::None,
+        }
+    }
+
+    // Indexing costs a cycle to fix up the high byte of the address. Reads skip it
+    // unless a page is crossed; writes and read-modify-writes always pay it.
+    // Called with PC at the operand.
+    pub fn index_penalty<T: MemIO>(&self, cpu: &CPU, mem: &mut T) -> usize {
+        if !matches!(self.1, AbsoluteX | AbsoluteY | IndirectIndexed) {
+            return 0;
+        }
+        match self.memory_access() {
+            MemAccess::Read => self.1.crosses_page(cpu, mem, cpu.pc) as usize,
+            MemAccess::Write | MemAccess::ReadModifyWrite => 1,
+            MemAccess::None => 0,
+        }
+    }
+
     pub fn execute<T: MemIO>(&self, cpu: &mut CPU, ram: &mut T) {
+        cpu.index_fix_up = self.index_penalty(cpu, ram) > 0;
         let ins = &self.0;
         let adr_mode = &self.1;
         match ins {
@@ -280,35 +542,35 @@ impl OpCode {
             }
             TAX => {
                 cpu.set_index_x(cpu.a);
-                cpu.remain_cycles += 1;
+                cpu.dummy_read(ram, cpu.pc);
             }
             TAY => {
                 cpu.set_index_y(cpu.a);
-                cpu.remain_cycles += 1;
+                cpu.dummy_read(ram, cpu.pc);
             }
             TXA => {
                 cpu.set_accumulator(cpu.x);
-                cpu.remain_cycles += 1;
+                cpu.dummy_read(ram, cpu.pc);
             }
             TYA => {
                 cpu.set_accumulator(cpu.y);
-                cpu.remain_cycles += 1;
+                cpu.dummy_read(ram, cpu.pc);
             }
             TSX => {
                 cpu.set_index_x(cpu.sp);
-                cpu.remain_cycles += 1;
+                cpu.dummy_read(ram, cpu.pc);
             }
             TXS => {
                 cpu.sp = cpu.x;
-                cpu.remain_cycles += 1;
+                cpu.dummy_read(ram, cpu.pc);
             }
             PHA => {
                 cpu.push_to_stack(ram, cpu.a);
             }
             PLA => {
+                cpu.dummy_read(ram, cpu.pc);
                 let byte = cpu.pull_from_stack(ram);
                 cpu.set_accumulator(byte);
-                cpu.remain_cycles += 1;
             }
             PHP => {
                 let byte = cpu.flags.get_as_u8();
@@ -317,11 +579,11 @@ impl OpCode {
                 cpu.push_to_stack(ram, byte);
             }
             PLP => {
+                cpu.dummy_read(ram, cpu.pc);
                 let byte = cpu.pull_from_stack(ram);
                 // https://wiki.nesdev.com/w/index.php/Status_flags#The_B_flag
                 let byte = byte & 0b11001111;
                 cpu.flags.set_as_u8(byte);
-                cpu.remain_cycles += 1;
             }
             AND => {
                 let byte = adr_mode.fetch(cpu, ram).unwrap();
@@ -342,22 +604,12 @@ impl OpCode {
                 cpu.flags.n = (byte >> 7 & 1) == 1;
             }
             ADC => {
-                let before_byte = adr_mode.fetch(cpu, ram).unwrap();
-                let (byte, overflowing1) = cpu.a.overflowing_add(before_byte);
-                let (byte, overflowing2) = byte.overflowing_add(cpu.flags.c as u8);
-                cpu.flags.c = overflowing1 || overflowing2;
-                cpu.flags.v =
-                    (((cpu.a ^ byte) & 0x80) != 0) && (((before_byte ^ byte) & 0x80) != 0);
-                cpu.set_accumulator(byte);
+                let byte = adr_mode.fetch(cpu, ram).unwrap();
+                cpu.adc_core(byte);
             }
             SBC => {
-                let before_byte = adr_mode.fetch(cpu, ram).unwrap();
-                let (byte, overflowing1) = cpu.a.overflowing_sub(before_byte);
-                let (byte, overflowing2) = byte.overflowing_sub(!cpu.flags.c as u8);
-                cpu.flags.c = !(overflowing1 || overflowing2);
-                cpu.flags.v =
-                    (((cpu.a ^ before_byte) & 0x80) != 0) && (((cpu.a ^ byte) & 0x80) != 0);
-                cpu.set_accumulator(byte);
+                let byte = adr_mode.fetch(cpu, ram).unwrap();
+                cpu.sbc_core(byte);
             }
             CMP => {
                 let byte = adr_mode.fetch(cpu, ram).unwrap();
@@ -380,46 +632,60 @@ impl OpCode {
             INC => {
                 let addr = adr_mode.get_address(cpu, ram).unwrap();
                 let byte = cpu.read_byte(ram, addr as usize);
+                cpu.dummy_write(ram, addr, byte);
                 let byte = byte.wrapping_add(1);
-                cpu.remain_cycles += 1;
                 cpu.set_zero_and_negative_flag(byte);
                 cpu.write_byte(ram, addr as usize, byte);
             }
+            TRB => {
+                let addr = adr_mode.get_address(cpu, ram).unwrap();
+                let byte = cpu.read_byte(ram, addr as usize);
+                cpu.flags.z = cpu.a & byte == 0;
+                cpu.dummy_read(ram, addr); // the 65C02 reads twice instead of writing twice
+                cpu.write_byte(ram, addr as usize, byte & !cpu.a);
+            }
+            TSB => {
+                let addr = adr_mode.get_address(cpu, ram).unwrap();
+                let byte = cpu.read_byte(ram, addr as usize);
+                cpu.flags.z = cpu.a & byte == 0;
+                cpu.dummy_read(ram, addr); // the 65C02 reads twice instead of writing twice
+                cpu.write_byte(ram, addr as usize, byte | cpu.a);
+            }
             INX => {
                 let byte = cpu.x;
                 let byte = byte.wrapping_add(1);
-                cpu.remain_cycles += 1;
+                cpu.dummy_read(ram, cpu.pc);
                 cpu.set_index_x(byte);
             }
             INY => {
                 let byte = cpu.y;
                 let byte = byte.wrapping_add(1);
-                cpu.remain_cycles += 1;
+                cpu.dummy_read(ram, cpu.pc);
                 cpu.set_index_y(byte);
             }
             DEC => {
                 let addr = adr_mode.get_address(cpu, ram).unwrap();
                 let byte = cpu.read_byte(ram, addr as usize);
+                cpu.dummy_write(ram, addr, byte);
                 let byte = byte.wrapping_sub(1);
-                cpu.remain_cycles += 1;
                 cpu.set_zero_and_negative_flag(byte);
                 cpu.write_byte(ram, addr as usize, byte);
             }
             DEX => {
                 let byte = cpu.x;
                 let byte = byte.wrapping_sub(1);
-                cpu.remain_cycles += 1;
+                cpu.dummy_read(ram, cpu.pc);
                 cpu.set_index_x(byte);
             }
             DEY => {
                 let byte = cpu.y;
                 let byte = byte.wrapping_sub(1);
-                cpu.remain_cycles += 1;
+                cpu.dummy_read(ram, cpu.pc);
                 cpu.set_index_y(byte);
             }
             ASL => {
-                cpu.remain_cycles += 1;
                 if let Accumulator = adr_mode {
+                    cpu.dummy_read(ram, cpu.pc);
                     let byte = adr_mode.fetch(cpu, ram).unwrap();
                     cpu.flags.c = byte >> 7 & 1 == 1; // old 7 bit
                     let byte = byte << 1;
@@ -427,6 +693,7 @@ impl OpCode {
                 } else {
                     let addr = adr_mode.get_address(cpu, ram).unwrap();
                     let byte = cpu.read_byte(ram, addr as usize);
+                    cpu.dummy_write(ram, addr, byte);
                     cpu.flags.c = byte >> 7 & 1 == 1; // old 7 bit
                     let byte = byte << 1;
                     cpu.set_zero_and_negative_flag(byte);
@@ -434,24 +701,25 @@ impl OpCode {
                 }
             }
             LSR => {
-                cpu.remain_cycles += 1;
                 if let Accumulator = adr_mode {
+                    cpu.dummy_read(ram, cpu.pc);
                     let byte = adr_mode.fetch(cpu, ram).unwrap();
-                    cpu.flags.c = byte >> 0 & 1 == 1; // old 0 bit
+                    cpu.flags.c = byte & 1 == 1; // old 0 bit
                     let byte = byte >> 1;
                     cpu.set_accumulator(byte);
                 } else {
                     let addr = adr_mode.get_address(cpu, ram).unwrap();
                     let byte = cpu.read_byte(ram, addr as usize);
-                    cpu.flags.c = byte >> 0 & 1 == 1; // old 0 bit
+                    cpu.dummy_write(ram, addr, byte);
+                    cpu.flags.c = byte & 1 == 1; // old 0 bit
                     let byte = byte >> 1;
                     cpu.set_zero_and_negative_flag(byte);
                     cpu.write_byte(ram, addr as usize, byte);
                 }
             }
             ROL => {
-                cpu.remain_cycles += 1;
                 if let Accumulator = adr_mode {
+                    cpu.dummy_read(ram, cpu.pc);
                     let byte = adr_mode.fetch(cpu, ram).unwrap();
                     let new_first_byte = cpu.flags.c as u8;
                     cpu.flags.c = byte >> 7 & 1 == 1; // old 7 bit
@@ -460,6 +728,7 @@ impl OpCode {
                 } else {
                     let addr = adr_mode.get_address(cpu, ram).unwrap();
                     let byte = cpu.read_byte(ram, addr as usize);
+                    cpu.dummy_write(ram, addr, byte);
                     let new_first_byte = cpu.flags.c as u8;
                     cpu.flags.c = byte >> 7 & 1 == 1; // old 7 bit
                     let byte = (byte << 1) | new_first_byte;
@@ -468,164 +737,134 @@ impl OpCode {
                 }
             }
             ROR => {
-                cpu.remain_cycles += 1;
                 if let Accumulator = adr_mode {
+                    cpu.dummy_read(ram, cpu.pc);
                     let byte = adr_mode.fetch(cpu, ram).unwrap();
                     let new_last_byte = (cpu.flags.c as u8) << 7;
-                    cpu.flags.c = byte >> 0 & 1 == 1; // old 0 bit
+                    cpu.flags.c = byte & 1 == 1; // old 0 bit
                     let byte = (byte >> 1) | new_last_byte;
                     cpu.set_accumulator(byte);
                 } else {
                     let addr = adr_mode.get_address(cpu, ram).unwrap();
                     let byte = cpu.read_byte(ram, addr as usize);
+                    cpu.dummy_write(ram, addr, byte);
                     let new_last_byte = (cpu.flags.c as u8) << 7;
-                    cpu.flags.c = byte >> 0 & 1 == 1; // old 0 bit
+                    cpu.flags.c = byte & 1 == 1; // old 0 bit
                     let byte = (byte >> 1) | new_last_byte;
                     cpu.set_zero_and_negative_flag(byte);
                     cpu.write_byte(ram, addr as usize, byte);
                 }
             }
             JMP => {
-                cpu.remain_cycles += 1;
+                // no internal cycle: 3 cycles absolute, 5 indirect
                 let addr = adr_mode.get_address(cpu, ram).unwrap();
                 cpu.pc = addr;
             }
             JSR => {
-                let addr = adr_mode.get_address(cpu, ram).unwrap();
-                let pc = cpu.pc - 1;
-                cpu.push_to_stack(ram, (pc >> 8) as u8);
-                cpu.push_to_stack(ram, (pc & 0xFF) as u8);
-                cpu.remain_cycles -= 1;
-                cpu.pc = addr;
+                // PC is pushed while it still points at the high byte of the target,
+                // which is fetched last
+                let addr_low = cpu.fetch_byte(ram);
+                cpu.dummy_read(ram, 0x0100 + cpu.sp as u16); // internal cycle on the stack
+                cpu.stack_write(ram, hi(cpu.pc));
+                cpu.stack_write(ram, lo(cpu.pc));
+                let addr_high = cpu.fetch_byte(ram);
+                cpu.pc = make_word(addr_low, addr_high);
             }
             RTS => {
-                cpu.remain_cycles += 1;
-                let pc =
-                    (cpu.pull_from_stack(ram) as u16) + ((cpu.pull_from_stack(ram) as u16) << 8);
-                cpu.pc = pc + 1;
+                cpu.dummy_read(ram, cpu.pc);
+                let lo = cpu.pull_from_stack(ram);
+                let pc = make_word(lo, cpu.stack_read(ram));
+                cpu.dummy_read(ram, pc); // PC increment
+                cpu.pc = pc.wrapping_add(1);
             }
             BCC => {
                 let addr = adr_mode.get_address(cpu, ram).unwrap();
-                if cpu.flags.c == false {
-                    cpu.remain_cycles += 1;
-                    if cpu.pc & 0xFF00 != addr & 0xFF00 {
-                        cpu.remain_cycles += 2;
-                    }
-                    cpu.pc = addr;
+                if !cpu.flags.c {
+                    cpu.take_branch(ram, addr);
                 }
             }
             BCS => {
                 let addr = adr_mode.get_address(cpu, ram).unwrap();
-                if cpu.flags.c == true {
-                    cpu.remain_cycles += 1;
-                    if cpu.pc & 0xFF00 != addr & 0xFF00 {
-                        cpu.remain_cycles += 2;
-                    }
-                    cpu.pc = addr;
+                if cpu.flags.c {
+                    cpu.take_branch(ram, addr);
                 }
             }
             BNE => {
                 let addr = adr_mode.get_address(cpu, ram).unwrap();
-                if cpu.flags.z == false {
-                    cpu.remain_cycles += 1;
-                    if cpu.pc & 0xFF00 != addr & 0xFF00 {
-                        cpu.remain_cycles += 2;
-                    }
-                    cpu.pc = addr;
+                if !cpu.flags.z {
+                    cpu.take_branch(ram, addr);
                 }
             }
             BEQ => {
                 let addr = adr_mode.get_address(cpu, ram).unwrap();
-                if cpu.flags.z == true {
-                    cpu.remain_cycles += 1;
-                    if cpu.pc & 0xFF00 != addr & 0xFF00 {
-                        cpu.remain_cycles += 2;
-                    }
-                    cpu.pc = addr;
+                if cpu.flags.z {
+                    cpu.take_branch(ram, addr);
                 }
             }
             BPL => {
                 let addr = adr_mode.get_address(cpu, ram).unwrap();
-                if cpu.flags.n == false {
-                    cpu.remain_cycles += 1;
-                    if cpu.pc & 0xFF00 != addr & 0xFF00 {
-                        cpu.remain_cycles += 2;
-                    }
-                    cpu.pc = addr;
+                if !cpu.flags.n {
+                    cpu.take_branch(ram, addr);
                 }
             }
             BMI => {
                 let addr = adr_mode.get_address(cpu, ram).unwrap();
-                if cpu.flags.n == true {
-                    cpu.remain_cycles += 1;
-                    if cpu.pc & 0xFF00 != addr & 0xFF00 {
-                        cpu.remain_cycles += 2;
-                    }
-                    cpu.pc = addr;
+                if cpu.flags.n {
+                    cpu.take_branch(ram, addr);
                 }
             }
             BVC => {
                 let addr = adr_mode.get_address(cpu, ram).unwrap();
-                if cpu.flags.v == false {
-                    cpu.remain_cycles += 1;
-                    if cpu.pc & 0xFF00 != addr & 0xFF00 {
-                        cpu.remain_cycles += 2;
-                    }
-                    cpu.pc = addr;
+                if !cpu.flags.v {
+                    cpu.take_branch(ram, addr);
                 }
             }
             BVS => {
                 let addr = adr_mode.get_address(cpu, ram).unwrap();
-                if cpu.flags.v == true {
-                    cpu.remain_cycles += 1;
-                    if cpu.pc & 0xFF00 != addr & 0xFF00 {
-                        cpu.remain_cycles += 2;
-                    }
-                    cpu.pc = addr;
+                if cpu.flags.v {
+                    cpu.take_branch(ram, addr);
                 }
             }
             CLC => {
-                cpu.remain_cycles += 1;
+                cpu.dummy_read(ram, cpu.pc);
                 cpu.flags.c = false;
             }
             CLD => {
-                cpu.remain_cycles += 1;
+                cpu.dummy_read(ram, cpu.pc);
                 cpu.flags.d = false;
             }
             CLI => {
-                cpu.remain_cycles += 1;
+                cpu.dummy_read(ram, cpu.pc);
                 cpu.flags.i = false;
             }
             CLV => {
-                cpu.remain_cycles += 1;
+                cpu.dummy_read(ram, cpu.pc);
                 cpu.flags.v = false;
             }
             SEC => {
-                cpu.remain_cycles += 1;
+                cpu.dummy_read(ram, cpu.pc);
                 cpu.flags.c = true;
             }
             SED => {
-                cpu.remain_cycles += 1;
+                cpu.dummy_read(ram, cpu.pc);
                 cpu.flags.d = true;
             }
             SEI => {
-                cpu.remain_cycles += 1;
+                cpu.dummy_read(ram, cpu.pc);
                 cpu.flags.i = true;
             }
             BRK => {
-                cpu.flags.b = true;
-                cpu.interrupt(ram, Interrupt::BRK);
+                cpu.handle_interrupt(ram, Interrupt::BRK);
             }
             NOP => {
-                cpu.remain_cycles += 1;
+                cpu.dummy_read(ram, cpu.pc);
             }
             RTI => {
+                cpu.dummy_read(ram, cpu.pc);
                 let flags = cpu.pull_from_stack(ram);
                 cpu.flags.set_as_u8(flags);
                 cpu.flags.b = false;
-                cpu.pc =
-                    (cpu.pull_from_stack(ram) as u16) + ((cpu.pull_from_stack(ram) as u16) << 8);
-                cpu.remain_cycles -= 1;
+                cpu.pc = make_word(cpu.stack_read(ram), cpu.stack_read(ram));
             }
             LAX => {
                 // LDA -> TAX
@@ -644,6 +883,7 @@ impl OpCode {
                 // DEC
                 let addr = adr_mode.get_address(cpu, ram).unwrap();
                 let byte = cpu.read_byte(ram, addr as usize);
+                cpu.dummy_write(ram, addr, byte);
                 let byte = byte.wrapping_sub(1);
                 cpu.write_byte(ram, addr as usize, byte);
 
@@ -651,30 +891,26 @@ impl OpCode {
                 cpu.flags.c = cpu.a >= byte;
                 cpu.flags.z = cpu.a == byte;
                 cpu.flags.n = cpu.a.wrapping_sub(byte) >> 7 & 1 == 1;
-                cpu.remain_cycles += 2;
             }
             ISB => {
                 // INC -> SBC
                 // INC
                 let addr = adr_mode.get_address(cpu, ram).unwrap();
                 let inc_byte = cpu.read_byte(ram, addr as usize);
+                cpu.dummy_write(ram, addr, inc_byte);
                 let inc_byte = inc_byte.wrapping_add(1);
                 cpu.set_zero_and_negative_flag(inc_byte);
                 cpu.write_byte(ram, addr as usize, inc_byte);
 
                 // SBC
-                let (byte, overflowing1) = cpu.a.overflowing_sub(inc_byte);
-                let (byte, overflowing2) = byte.overflowing_sub(!cpu.flags.c as u8);
-                cpu.flags.c = !(overflowing1 || overflowing2);
-                cpu.flags.v = (((cpu.a ^ inc_byte) & 0x80) != 0) && (((cpu.a ^ byte) & 0x80) != 0);
-                cpu.set_accumulator(byte);
-                cpu.remain_cycles += 2;
+                cpu.sbc_core(inc_byte);
             }
             RLA => {
                 // ROL -> AND
                 // ROL
                 let addr = adr_mode.get_address(cpu, ram).unwrap();
                 let byte = cpu.read_byte(ram, addr as usize);
+                cpu.dummy_write(ram, addr, byte);
                 let new_first_byte = cpu.flags.c as u8;
                 cpu.flags.c = byte >> 7 & 1 == 1; // old 7 bit
                 let byte = (byte << 1) | new_first_byte;
@@ -683,33 +919,28 @@ impl OpCode {
 
                 // AND
                 cpu.set_accumulator(cpu.a & byte);
-                cpu.remain_cycles += 2;
             }
             RRA => {
                 // ROR -> ADC
                 // ROR
                 let addr = adr_mode.get_address(cpu, ram).unwrap();
                 let byte = cpu.read_byte(ram, addr as usize);
+                cpu.dummy_write(ram, addr, byte);
                 let new_last_byte = (cpu.flags.c as u8) << 7;
-                cpu.flags.c = byte >> 0 & 1 == 1; // old 0 bit
+                cpu.flags.c = byte & 1 == 1; // old 0 bit
                 let ror_byte = (byte >> 1) | new_last_byte;
                 cpu.set_zero_and_negative_flag(ror_byte);
                 cpu.write_byte(ram, addr as usize, ror_byte);
 
                 // ADC
-                let (byte, overflowing1) = cpu.a.overflowing_add(ror_byte);
-                let (byte, overflowing2) = byte.overflowing_add(cpu.flags.c as u8);
-                cpu.flags.c = overflowing1 || overflowing2;
-                cpu.flags.v = (((cpu.a ^ byte) & 0x80) != 0) && (((ror_byte ^ byte) & 0x80) != 0);
-                cpu.set_accumulator(byte);
-                cpu.remain_cycles += 2;
+                cpu.adc_core(ror_byte);
             }
             SLO => {
                 // ASL -> ORA
                 // ASL
-                cpu.remain_cycles += 1;
                 let addr = adr_mode.get_address(cpu, ram).unwrap();
                 let byte = cpu.read_byte(ram, addr as usize);
+                cpu.dummy_write(ram, addr, byte);
                 cpu.flags.c = byte >> 7 & 1 == 1; // old 7 bit
                 let byte = byte << 1;
                 cpu.set_zero_and_negative_flag(byte);
@@ -717,21 +948,79 @@ impl OpCode {
 
                 // ORA
                 cpu.set_accumulator(cpu.a | byte);
-                cpu.remain_cycles += 2;
             }
             SRE => {
                 // LSR -> EOR
                 // LSR
                 let addr = adr_mode.get_address(cpu, ram).unwrap();
                 let byte = cpu.read_byte(ram, addr as usize);
-                cpu.flags.c = byte >> 0 & 1 == 1; // old 0 bit
+                cpu.dummy_write(ram, addr, byte);
+                cpu.flags.c = byte & 1 == 1; // old 0 bit
                 let byte = byte >> 1;
                 cpu.set_zero_and_negative_flag(byte);
                 cpu.write_byte(ram, addr as usize, byte);
 
                 // EOR
                 cpu.set_accumulator(cpu.a ^ byte);
-                cpu.remain_cycles += 2;
+            }
+            ANC => {
+                // AND, then C takes the new bit 7 as if shifted out by ASL
+                let byte = adr_mode.fetch(cpu, ram).unwrap();
+                cpu.set_accumulator(cpu.a & byte);
+                cpu.flags.c = cpu.flags.n;
+            }
+            ALR => {
+                // AND -> LSR A
+                let byte = adr_mode.fetch(cpu, ram).unwrap();
+                let byte = cpu.a & byte;
+                cpu.flags.c = byte & 1 == 1; // old 0 bit
+                cpu.set_accumulator(byte >> 1);
+            }
+            ARR => {
+                // AND -> ROR A, but C and V don't come from the rotate: the result
+                // also passes through the adder, leaving C = bit 6 and V = bit 6 ^ bit 5
+                let byte = adr_mode.fetch(cpu, ram).unwrap();
+                let byte = cpu.a & byte;
+                let byte = (byte >> 1) | (cpu.flags.c as u8) << 7;
+                cpu.set_accumulator(byte);
+                cpu.flags.c = byte >> 6 & 1 == 1;
+                cpu.flags.v = (byte >> 6 ^ byte >> 5) & 1 == 1;
+            }
+            ANE => {
+                let byte = adr_mode.fetch(cpu, ram).unwrap();
+                cpu.set_accumulator((cpu.a | cpu.ane_magic) & cpu.x & byte);
+            }
+            SBX => {
+                // X = (A & X) - imm, flagged like CPX. V is left alone.
+                let byte = adr_mode.fetch(cpu, ram).unwrap();
+                let and = cpu.a & cpu.x;
+                cpu.flags.c = and >= byte;
+                cpu.set_index_x(and.wrapping_sub(byte));
+            }
+            LAS => {
+                let byte = adr_mode.fetch(cpu, ram).unwrap() & cpu.sp;
+                cpu.sp = byte;
+                cpu.set_index_x(byte);
+                cpu.set_accumulator(byte);
+            }
+            SHA => {
+                adr_mode.store_high_and(cpu, ram, cpu.a & cpu.x);
+            }
+            SHX => {
+                adr_mode.store_high_and(cpu, ram, cpu.x);
+            }
+            SHY => {
+                adr_mode.store_high_and(cpu, ram, cpu.y);
+            }
+            TAS => {
+                cpu.sp = cpu.a & cpu.x;
+                adr_mode.store_high_and(cpu, ram, cpu.sp);
+            }
+            JAM => {
+                // locks up until reset, with PC left on the opcode
+                cpu.dummy_read(ram, cpu.pc);
+                cpu.halted = true;
+                cpu.pc = cpu.pc.wrapping_sub(1);
             }
             SKB => {
                 adr_mode.fetch(cpu, ram).unwrap();
@@ -742,46 +1031,27 @@ impl OpCode {
         }
     }
 
-    #[cfg(not(feature = "logging"))]
+    #[cfg(not(any(feature = "logging", feature = "log")))]
     #[allow(dead_code)]
     pub fn log<T: MemIO>(&self, _cpu: &mut CPU, _mem: &mut T) -> String {
         "".to_string()
     }
 
-    #[cfg(feature = "logging")]
+    #[cfg(any(feature = "logging", feature = "log"))]
     pub fn log<T: MemIO>(&self, cpu: &mut CPU, mem: &mut T) -> String {
-        let ins_byte = mem.read_byte_without_effect((cpu.pc - 1) as usize);
-        let op = &OPCODES[ins_byte as usize].unwrap();
+        let ins_byte = mem.read_byte_without_effect(cpu.pc.wrapping_sub(1) as usize);
+        let op = self;
 
         let ins = op.0;
         let adr_mode = op.1;
         let ofc = op.2;
 
-        let ins_name = match ins {
-            SKB | IGN => "NOP".to_string(),
-            _ => {
-                format!("{:?}", ins)
-            }
-        };
+        let ins_name = ins.mnemonic();
 
-        let need_byte_count = match adr_mode {
-            Implied => 0,
-            Accumulator => 0,
-            Immediate => 1,
-            ZeroPage => 1,
-            ZeroPageX => 1,
-            ZeroPageY => 1,
-            Relative => 1,
-            Absolute => 2,
-            AbsoluteX => 2,
-            AbsoluteY => 2,
-            Indirect => 2,
-            IndexedIndirect => 1,
-            IndirectIndexed => 1,
-        };
+        let need_byte_count = adr_mode.operand_len() as u16;
         let mut bytes = vec![];
         for i in 0..need_byte_count {
-            bytes.push(mem.read_byte_without_effect((cpu.pc + i) as usize));
+            bytes.push(mem.read_byte_without_effect(cpu.pc.wrapping_add(i) as usize));
         }
 
         let (mut addr_str, addr) = match adr_mode {
@@ -797,58 +1067,60 @@ impl OpCode {
                 format!("${:02X},Y", bytes[0]),
                 Some((bytes[0].wrapping_add(cpu.y)) as u16),
             ),
-            Relative => (
-                format!(
-                    "${:04X}",
-                    (((cpu.pc + 1) as i32) + (bytes[0] as i8) as i32) as u16
-                ),
-                Some(cpu.pc + 1 + bytes[0] as u16),
-            ),
+            Relative => {
+                let addr = cpu.pc.wrapping_add(1).wrapping_add(bytes[0] as i8 as u16);
+                (format!("${:04X}", addr), Some(addr))
+            }
             Absolute => (
-                format!("${:04X}", bytes[0] as u16 + ((bytes[1] as u16) << 8)),
-                Some(bytes[0] as u16 + ((bytes[1] as u16) << 8)),
+                format!("${:04X}", make_word(bytes[0], bytes[1])),
+                Some(make_word(bytes[0], bytes[1])),
             ),
             AbsoluteX => (
-                format!("${:04X},X", bytes[0] as u16 + ((bytes[1] as u16) << 8)),
-                Some(bytes[0] as u16 + ((bytes[1] as u16) << 8).wrapping_add(cpu.x as u16)),
+                format!("${:04X},X", make_word(bytes[0], bytes[1])),
+                Some(make_word(bytes[0], bytes[1]).wrapping_add(cpu.x as u16)),
             ),
             AbsoluteY => (
-                format!("${:04X},Y", bytes[0] as u16 + ((bytes[1] as u16) << 8)),
-                Some(
-                    (bytes[0] as u16)
-                        .wrapping_add(((bytes[1] as u16) << 8).wrapping_add(cpu.y as u16)),
-                ),
+                format!("${:04X},Y", make_word(bytes[0], bytes[1])),
+                Some(make_word(bytes[0], bytes[1]).wrapping_add(cpu.y as u16)),
             ),
             Indirect => {
-                let in_addr = bytes[0] as u16 + ((bytes[1] as u16) << 8);
-                let addr = mem.read_byte_without_effect(in_addr as usize) as u16
-                    + ((mem.read_byte_without_effect((in_addr.wrapping_add(1)) as usize) as u16)
-                        << 8);
-                (
-                    format!("(${:04X})", bytes[0] as u16 + ((bytes[1] as u16) << 8)),
-                    Some(addr),
-                )
+                let in_addr = make_word(bytes[0], bytes[1]);
+                let addr = make_word(
+                    mem.read_byte_without_effect(in_addr as usize),
+                    mem.read_byte_without_effect(in_addr.wrapping_add(1) as usize),
+                );
+                (format!("(${:04X})", in_addr), Some(addr))
             }
             IndexedIndirect => {
                 let in_addr = bytes[0].wrapping_add(cpu.x);
-                let addr = mem.read_byte_without_effect(in_addr as usize) as u16
-                    + ((mem.read_byte_without_effect((in_addr.wrapping_add(1)) as usize) as u16)
-                        << 8);
+                let addr = make_word(
+                    mem.read_byte_without_effect(in_addr as usize),
+                    mem.read_byte_without_effect(in_addr.wrapping_add(1) as usize),
+                );
                 (format!("(${:02X},X)", bytes[0]), Some(addr))
             }
             IndirectIndexed => {
                 let in_addr = bytes[0];
-                let addr = (mem.read_byte_without_effect(in_addr as usize) as u16
-                    + ((mem.read_byte_without_effect((in_addr.wrapping_add(1)) as usize) as u16)
-                        << 8))
-                    .wrapping_add(cpu.y as u16);
+                let addr = make_word(
+                    mem.read_byte_without_effect(in_addr as usize),
+                    mem.read_byte_without_effect(in_addr.wrapping_add(1) as usize),
+                )
+                .wrapping_add(cpu.y as u16);
                 (format!("(${:02X}),Y", bytes[0]), Some(addr))
             }
+            IndirectZeroPage => {
+                let in_addr = bytes[0];
+                let addr = make_word(
+                    mem.read_byte_without_effect(in_addr as usize),
+                    mem.read_byte_without_effect(in_addr.wrapping_add(1) as usize),
+                );
+                (format!("(${:02X})", bytes[0]), Some(addr))
+            }
         };
         match ins {
             LDA | LDX | LDY | STA | STX | STY | BIT | ORA | AND | EOR | ADC | SBC | CMP | CPX
             | CPY | LSR | ASL | ROR | ROL | INC | DEC | LAX | SAX | DCP | ISB | RLA | RRA | SLO
-            | SRE | SKB | IGN => match adr_mode {
+            | SRE | LAS | SHA | SHX | SHY | TAS | SKB | IGN => match adr_mode {
                 Implied | Accumulator | Immediate => {}
                 ZeroPageX => {
                     addr_str = format!("{:} @ {:02X}", addr_str, (bytes[0]).wrapping_add(cpu.x));
@@ -870,8 +1142,7 @@ impl OpCode {
                     addr_str = format!(
                         "{:} @ {:04X}",
                         addr_str,
-                        (bytes[0] as u16)
-                            .wrapping_add(((bytes[1] as u16) << 8).wrapping_add(cpu.x as u16))
+                        make_word(bytes[0], bytes[1]).wrapping_add(cpu.x as u16)
                     );
                     addr_str = format!(
                         "{:} = {:02X}",
@@ -883,8 +1154,7 @@ impl OpCode {
                     addr_str = format!(
                         "{:} @ {:04X}",
                         addr_str,
-                        (bytes[0] as u16)
-                            .wrapping_add(((bytes[1] as u16) << 8).wrapping_add(cpu.y as u16))
+                        make_word(bytes[0], bytes[1]).wrapping_add(cpu.y as u16)
                     );
                     addr_str = format!(
                         "{:} = {:02X}",
@@ -895,10 +1165,10 @@ impl OpCode {
                 IndexedIndirect => {
                     let in_addr = bytes[0].wrapping_add(cpu.x);
                     addr_str = format!("{:} @ {:02X}", addr_str, in_addr);
-                    let indexed_addr = mem.read_byte_without_effect(in_addr as usize) as u16
-                        + ((mem.read_byte_without_effect((in_addr.wrapping_add(1)) as usize)
-                            as u16)
-                            << 8);
+                    let indexed_addr = make_word(
+                        mem.read_byte_without_effect(in_addr as usize),
+                        mem.read_byte_without_effect(in_addr.wrapping_add(1) as usize),
+                    );
                     addr_str = format!("{:} = {:04X}", addr_str, indexed_addr);
                     addr_str = format!(
                         "{:} = {:02X}",
@@ -908,10 +1178,10 @@ impl OpCode {
                 }
                 IndirectIndexed => {
                     let in_addr = bytes[0];
-                    let indirected_addr = mem.read_byte_without_effect(in_addr as usize) as u16
-                        + ((mem.read_byte_without_effect((in_addr.wrapping_add(1)) as usize)
-                            as u16)
-                            << 8);
+                    let indirected_addr = make_word(
+                        mem.read_byte_without_effect(in_addr as usize),
+                        mem.read_byte_without_effect(in_addr.wrapping_add(1) as usize),
+                    );
                     addr_str = format!("{:} = {:04X}", addr_str, indirected_addr);
                     addr_str = format!(
                         "{:} @ {:04X}",
@@ -932,10 +1202,8 @@ impl OpCode {
                     )
                 }
             },
-            JMP => {
-                if adr_mode == Indirect {
-                    addr_str = format!("{:} = {:04X}", addr_str, addr.unwrap());
-                }
+            JMP if adr_mode == Indirect => {
+                addr_str = format!("{:} = {:04X}", addr_str, addr.unwrap());
             }
             _ => {}
         }
@@ -960,7 +1228,7 @@ use Officiality::*;
 pub const OPCODES: [Option<OpCode>; 0x100] = [
     /* 0x00 */ Some(OpCode(BRK, Implied, Official)),
     /* 0x01 */ Some(OpCode(ORA, IndexedIndirect, Official)),
-    /* 0x02 */ None,
+    /* 0x02 */ Some(OpCode(JAM, Implied, Unofficial)),
     /* 0x03 */ Some(OpCode(SLO, IndexedIndirect, Unofficial)),
     /* 0x04 */ Some(OpCode(IGN, ZeroPage, Unofficial)),
     /* 0x05 */ Some(OpCode(ORA, ZeroPage, Official)),
@@ -969,14 +1237,14 @@ pub const OPCODES: [Option<OpCode>; 0x100] = [
     /* 0x08 */ Some(OpCode(PHP, Implied, Official)),
     /* 0x09 */ Some(OpCode(ORA, Immediate, Official)),
     /* 0x0A */ Some(OpCode(ASL, Accumulator, Official)),
-    /* 0x0B */ None,
+    /* 0x0B */ Some(OpCode(ANC, Immediate, Unofficial)),
     /* 0x0C */ Some(OpCode(IGN, Absolute, Unofficial)),
     /* 0x0D */ Some(OpCode(ORA, Absolute, Official)),
     /* 0x0E */ Some(OpCode(ASL, Absolute, Official)),
     /* 0x0F */ Some(OpCode(SLO, Absolute, Unofficial)),
     /* 0x10 */ Some(OpCode(BPL, Relative, Official)),
     /* 0x11 */ Some(OpCode(ORA, IndirectIndexed, Official)),
-    /* 0x12 */ None,
+    /* 0x12 */ Some(OpCode(JAM, Implied, Unofficial)),
     /* 0x13 */ Some(OpCode(SLO, IndirectIndexed, Unofficial)),
     /* 0x14 */ Some(OpCode(IGN, ZeroPageX, Unofficial)),
     /* 0x15 */ Some(OpCode(ORA, ZeroPageX, Official)),
@@ -992,7 +1260,7 @@ pub const OPCODES: [Option<OpCode>; 0x100] = [
     /* 0x1F */ Some(OpCode(SLO, AbsoluteX, Unofficial)),
     /* 0x20 */ Some(OpCode(JSR, Absolute, Official)),
     /* 0x21 */ Some(OpCode(AND, IndexedIndirect, Official)),
-    /* 0x22 */ None,
+    /* 0x22 */ Some(OpCode(JAM, Implied, Unofficial)),
     /* 0x23 */ Some(OpCode(RLA, IndexedIndirect, Unofficial)),
     /* 0x24 */ Some(OpCode(BIT, ZeroPage, Official)),
     /* 0x25 */ Some(OpCode(AND, ZeroPage, Official)),
@@ -1001,14 +1269,14 @@ pub const OPCODES: [Option<OpCode>; 0x100] = [
     /* 0x28 */ Some(OpCode(PLP, Implied, Official)),
     /* 0x29 */ Some(OpCode(AND, Immediate, Official)),
     /* 0x2A */ Some(OpCode(ROL, Accumulator, Official)),
-    /* 0x2B */ None,
+    /* 0x2B */ Some(OpCode(ANC, Immediate, Unofficial)),
     /* 0x2C */ Some(OpCode(BIT, Absolute, Official)),
     /* 0x2D */ Some(OpCode(AND, Absolute, Official)),
     /* 0x2E */ Some(OpCode(ROL, Absolute, Official)),
     /* 0x2F */ Some(OpCode(RLA, Absolute, Unofficial)),
     /* 0x30 */ Some(OpCode(BMI, Relative, Official)),
     /* 0x31 */ Some(OpCode(AND, IndirectIndexed, Official)),
-    /* 0x32 */ None,
+    /* 0x32 */ Some(OpCode(JAM, Implied, Unofficial)),
     /* 0x33 */ Some(OpCode(RLA, IndirectIndexed, Unofficial)),
     /* 0x34 */ Some(OpCode(IGN, ZeroPageX, Unofficial)),
     /* 0x35 */ Some(OpCode(AND, ZeroPageX, Official)),
@@ -1024,7 +1292,7 @@ pub const OPCODES: [Option<OpCode>; 0x100] = [
     /* 0x3F */ Some(OpCode(RLA, AbsoluteX, Unofficial)),
     /* 0x40 */ Some(OpCode(RTI, Implied, Official)),
     /* 0x41 */ Some(OpCode(EOR, IndexedIndirect, Official)),
-    /* 0x42 */ None,
+    /* 0x42 */ Some(OpCode(JAM, Implied, Unofficial)),
     /* 0x43 */ Some(OpCode(SRE, IndexedIndirect, Unofficial)),
     /* 0x44 */ Some(OpCode(IGN, ZeroPage, Unofficial)),
     /* 0x45 */ Some(OpCode(EOR, ZeroPage, Official)),
@@ -1033,14 +1301,14 @@ pub const OPCODES: [Option<OpCode>; 0x100] = [
     /* 0x48 */ Some(OpCode(PHA, Implied, Official)),
     /* 0x49 */ Some(OpCode(EOR, Immediate, Official)),
     /* 0x4A */ Some(OpCode(LSR, Accumulator, Official)),
-    /* 0x4B */ None,
+    /* 0x4B */ Some(OpCode(ALR, Immediate, Unofficial)),
     /* 0x4C */ Some(OpCode(JMP, Absolute, Official)),
     /* 0x4D */ Some(OpCode(EOR, Absolute, Official)),
     /* 0x4E */ Some(OpCode(LSR, Absolute, Official)),
     /* 0x4F */ Some(OpCode(SRE, Absolute, Unofficial)),
     /* 0x50 */ Some(OpCode(BVC, Relative, Official)),
     /* 0x51 */ Some(OpCode(EOR, IndirectIndexed, Official)),
-    /* 0x52 */ None,
+    /* 0x52 */ Some(OpCode(JAM, Implied, Unofficial)),
     /* 0x53 */ Some(OpCode(SRE, IndirectIndexed, Unofficial)),
     /* 0x54 */ Some(OpCode(IGN, ZeroPageX, Unofficial)),
     /* 0x55 */ Some(OpCode(EOR, ZeroPageX, Official)),
@@ -1056,7 +1324,7 @@ pub const OPCODES: [Option<OpCode>; 0x100] = [
     /* 0x5F */ Some(OpCode(SRE, AbsoluteX, Unofficial)),
     /* 0x60 */ Some(OpCode(RTS, Implied, Official)),
     /* 0x61 */ Some(OpCode(ADC, IndexedIndirect, Official)),
-    /* 0x62 */ None,
+    /* 0x62 */ Some(OpCode(JAM, Implied, Unofficial)),
     /* 0x63 */ Some(OpCode(RRA, IndexedIndirect, Unofficial)),
     /* 0x64 */ Some(OpCode(IGN, ZeroPage, Unofficial)),
     /* 0x65 */ Some(OpCode(ADC, ZeroPage, Official)),
@@ -1065,14 +1333,14 @@ pub const OPCODES: [Option<OpCode>; 0x100] = [
     /* 0x68 */ Some(OpCode(PLA, Implied, Official)),
     /* 0x69 */ Some(OpCode(ADC, Immediate, Official)),
     /* 0x6A */ Some(OpCode(ROR, Accumulator, Official)),
-    /* 0x6B */ None,
+    /* 0x6B */ Some(OpCode(ARR, Immediate, Unofficial)),
     /* 0x6C */ Some(OpCode(JMP, Indirect, Official)),
     /* 0x6D */ Some(OpCode(ADC, Absolute, Official)),
     /* 0x6E */ Some(OpCode(ROR, Absolute, Official)),
     /* 0x6F */ Some(OpCode(RRA, Absolute, Unofficial)),
     /* 0x70 */ Some(OpCode(BVS, Relative, Official)),
     /* 0x71 */ Some(OpCode(ADC, IndirectIndexed, Official)),
-    /* 0x72 */ None,
+    /* 0x72 */ Some(OpCode(JAM, Implied, Unofficial)),
     /* 0x73 */ Some(OpCode(RRA, IndirectIndexed, Unofficial)),
     /* 0x74 */ Some(OpCode(IGN, ZeroPageX, Unofficial)),
     /* 0x75 */ Some(OpCode(ADC, ZeroPageX, Official)),
@@ -1097,15 +1365,15 @@ pub const OPCODES: [Option<OpCode>; 0x100] = [
     /* 0x88 */ Some(OpCode(DEY, Implied, Official)),
     /* 0x89 */ Some(OpCode(SKB, Immediate, Unofficial)),
     /* 0x8A */ Some(OpCode(TXA, Implied, Official)),
-    /* 0x8B */ None,
+    /* 0x8B */ Some(OpCode(ANE, Immediate, Unofficial)),
     /* 0x8C */ Some(OpCode(STY, Absolute, Official)),
     /* 0x8D */ Some(OpCode(STA, Absolute, Official)),
     /* 0x8E */ Some(OpCode(STX, Absolute, Official)),
     /* 0x8F */ Some(OpCode(SAX, Absolute, Unofficial)),
     /* 0x90 */ Some(OpCode(BCC, Relative, Official)),
     /* 0x91 */ Some(OpCode(STA, IndirectIndexed, Official)),
-    /* 0x92 */ None,
-    /* 0x93 */ None,
+    /* 0x92 */ Some(OpCode(JAM, Implied, Unofficial)),
+    /* 0x93 */ Some(OpCode(SHA, IndirectIndexed, Unofficial)),
     /* 0x94 */ Some(OpCode(STY, ZeroPageX, Official)),
     /* 0x95 */ Some(OpCode(STA, ZeroPageX, Official)),
     /* 0x96 */ Some(OpCode(STX, ZeroPageY, Official)),
@@ -1113,11 +1381,11 @@ pub const OPCODES: [Option<OpCode>; 0x100] = [
     /* 0x98 */ Some(OpCode(TYA, Implied, Official)),
     /* 0x99 */ Some(OpCode(STA, AbsoluteY, Official)),
     /* 0x9A */ Some(OpCode(TXS, Implied, Official)),
-    /* 0x9B */ None,
-    /* 0x9C */ None,
+    /* 0x9B */ Some(OpCode(TAS, AbsoluteY, Unofficial)),
+    /* 0x9C */ Some(OpCode(SHY, AbsoluteX, Unofficial)),
     /* 0x9D */ Some(OpCode(STA, AbsoluteX, Official)),
-    /* 0x9E */ None,
-    /* 0x9F */ None,
+    /* 0x9E */ Some(OpCode(SHX, AbsoluteY, Unofficial)),
+    /* 0x9F */ Some(OpCode(SHA, AbsoluteY, Unofficial)),
     /* 0xA0 */ Some(OpCode(LDY, Immediate, Official)),
     /* 0xA1 */ Some(OpCode(LDA, IndexedIndirect, Official)),
     /* 0xA2 */ Some(OpCode(LDX, Immediate, Official)),
@@ -1136,7 +1404,7 @@ pub const OPCODES: [Option<OpCode>; 0x100] = [
     /* 0xAF */ Some(OpCode(LAX, Absolute, Unofficial)),
     /* 0xB0 */ Some(OpCode(BCS, Relative, Official)),
     /* 0xB1 */ Some(OpCode(LDA, IndirectIndexed, Official)),
-    /* 0xB2 */ None,
+    /* 0xB2 */ Some(OpCode(JAM, Implied, Unofficial)),
     /* 0xB3 */ Some(OpCode(LAX, IndirectIndexed, Unofficial)),
     /* 0xB4 */ Some(OpCode(LDY, ZeroPageX, Official)),
     /* 0xB5 */ Some(OpCode(LDA, ZeroPageX, Official)),
@@ -1145,7 +1413,7 @@ pub const OPCODES: [Option<OpCode>; 0x100] = [
     /* 0xB8 */ Some(OpCode(CLV, Implied, Official)),
     /* 0xB9 */ Some(OpCode(LDA, AbsoluteY, Official)),
     /* 0xBA */ Some(OpCode(TSX, Implied, Official)),
-    /* 0xBB */ None,
+    /* 0xBB */ Some(OpCode(LAS, AbsoluteY, Unofficial)),
     /* 0xBC */ Some(OpCode(LDY, AbsoluteX, Official)),
     /* 0xBD */ Some(OpCode(LDA, AbsoluteX, Official)),
     /* 0xBE */ Some(OpCode(LDX, AbsoluteY, Official)),
@@ -1161,14 +1429,14 @@ pub const OPCODES: [Option<OpCode>; 0x100] = [
     /* 0xC8 */ Some(OpCode(INY, Implied, Official)),
     /* 0xC9 */ Some(OpCode(CMP, Immediate, Official)),
     /* 0xCA */ Some(OpCode(DEX, Implied, Official)),
-    /* 0xCB */ None,
+    /* 0xCB */ Some(OpCode(SBX, Immediate, Unofficial)),
     /* 0xCC */ Some(OpCode(CPY, Absolute, Official)),
     /* 0xCD */ Some(OpCode(CMP, Absolute, Official)),
     /* 0xCE */ Some(OpCode(DEC, Absolute, Official)),
     /* 0xCF */ Some(OpCode(DCP, Absolute, Unofficial)),
     /* 0xD0 */ Some(OpCode(BNE, Relative, Official)),
     /* 0xD1 */ Some(OpCode(CMP, IndirectIndexed, Official)),
-    /* 0xD2 */ None,
+    /* 0xD2 */ Some(OpCode(JAM, Implied, Unofficial)),
     /* 0xD3 */ Some(OpCode(DCP, IndirectIndexed, Unofficial)),
     /* 0xD4 */ Some(OpCode(IGN, ZeroPageX, Unofficial)),
     /* 0xD5 */ Some(OpCode(CMP, ZeroPageX, Official)),
@@ -1200,7 +1468,7 @@ pub const OPCODES: [Option<OpCode>; 0x100] = [
     /* 0xEF */ Some(OpCode(ISB, Absolute, Unofficial)),
     /* 0xF0 */ Some(OpCode(BEQ, Relative, Official)),
     /* 0xF1 */ Some(OpCode(SBC, IndirectIndexed, Official)),
-    /* 0xF2 */ None,
+    /* 0xF2 */ Some(OpCode(JAM, Implied, Unofficial)),
     /* 0xF3 */ Some(OpCode(ISB, IndirectIndexed, Unofficial)),
     /* 0xF4 */ Some(OpCode(IGN, ZeroPageX, Unofficial)),
     /* 0xF5 */ Some(OpCode(SBC, ZeroPageX, Official)),
@@ -1216,6 +1484,107 @@ pub const OPCODES: [Option<OpCode>; 0x100] = [
     /* 0xFF */ Some(OpCode(ISB, AbsoluteX, Unofficial)),
 ];
 
+// (byte, opcode, base cycles) of the opcodes the 65C02 adds. When `cpu.cmos` is set
+// they replace what these slots mean on the NMOS part (IGN/SKB, JAM, ...).
+const CMOS_OPCODES: [(u8, OpCode, u8); 12] = [
+    (0x04, OpCode(TSB, ZeroPage, Official), 5),
+    (0x0C, OpCode(TSB, Absolute, Official), 6),
+    (0x12, OpCode(ORA, IndirectZeroPage, Official), 5),
+    (0x14, OpCode(TRB, ZeroPage, Official), 5),
+    (0x1C, OpCode(TRB, Absolute, Official), 6),
+    (0x32, OpCode(AND, IndirectZeroPage, Official), 5),
+    (0x52, OpCode(EOR, IndirectZeroPage, Official), 5),
+    (0x72, OpCode(ADC, IndirectZeroPage, Official), 5),
+    (0x92, OpCode(STA, IndirectZeroPage, Official), 5),
+    (0xB2, OpCode(LDA, IndirectZeroPage, Official), 5),
+    (0xD2, OpCode(CMP, IndirectZeroPage, Official), 5),
+    (0xF2, OpCode(SBC, IndirectZeroPage, Official), 5),
+];
+
+pub fn cmos_opcode(byte: u8) -> Option<OpCode> {
+    CMOS_OPCODES
+        .iter()
+        .find(|&&(cmos_byte, _, _)| cmos_byte == byte)
+        .map(|&(_, op, _)| op)
+}
+
+pub fn decode(byte: u8, cmos: bool) -> Option<OpCode> {
+    match cmos_opcode(byte) {
+        Some(op) if cmos => Some(op),
+        _ => OPCODES[byte as usize],
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpcodeInfo {
+    Undefined,
+    Official(Instruction, AddressingMode),
+    Unofficial(Instruction, AddressingMode),
+}
+
+// What an NMOS opcode byte decodes to, without unwrapping `OPCODES`.
+pub fn opcode_info(byte: u8) -> OpcodeInfo {
+    match OPCODES[byte as usize] {
+        Some(OpCode(ins, mode, Official)) => OpcodeInfo::Official(ins, mode),
+        Some(OpCode(ins, mode, Unofficial)) => OpcodeInfo::Unofficial(ins, mode),
+        None => OpcodeInfo::Undefined,
+    }
+}
+
+// Base cycles of an NMOS opcode byte, 0 if it is undefined.
+pub fn base_cycles(opcode: u8) -> u8 {
+    CYCLES[opcode as usize]
+}
+
+// Base cycles by instruction and addressing mode, worked out at compile time from
+// `CYCLES` and `CMOS_OPCODES` so `OpCode::base_cycles` needn't look for its byte.
+const INSTRUCTION_COUNT: usize = IGN as usize + 1; // IGN is the last variant
+const MODE_COUNT: usize = IndirectZeroPage as usize + 1;
+const OPCODE_CYCLES: [[u8; MODE_COUNT]; INSTRUCTION_COUNT] = opcode_cycles();
+
+const fn opcode_cycles() -> [[u8; MODE_COUNT]; INSTRUCTION_COUNT] {
+    let mut table = [[0; MODE_COUNT]; INSTRUCTION_COUNT];
+    let mut byte = 0;
+    while byte < OPCODES.len() {
+        if let Some(OpCode(ins, mode, _)) = OPCODES[byte] {
+            let cycles = &mut table[ins as usize][mode as usize];
+            // opcodes that share an instruction and mode must share a count
+            assert!(*cycles == 0 || *cycles == CYCLES[byte]);
+            *cycles = CYCLES[byte];
+        }
+        byte += 1;
+    }
+    let mut i = 0;
+    while i < CMOS_OPCODES.len() {
+        let (_, OpCode(ins, mode, _), cycles) = CMOS_OPCODES[i];
+        table[ins as usize][mode as usize] = cycles;
+        i += 1;
+    }
+    table
+}
+
+// Base cycle counts per opcode, before page-crossing and branch penalties.
+// Undefined opcodes are 0.
+#[rustfmt::skip]
+pub const CYCLES: [u8; 0x100] = [
+    /* 0x00 */ 7, 6, 2, 8, 3, 3, 5, 5, 3, 2, 2, 2, 4, 4, 6, 6,
+    /* 0x10 */ 2, 5, 2, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,
+    /* 0x20 */ 6, 6, 2, 8, 3, 3, 5, 5, 4, 2, 2, 2, 4, 4, 6, 6,
+    /* 0x30 */ 2, 5, 2, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,
+    /* 0x40 */ 6, 6, 2, 8, 3, 3, 5, 5, 3, 2, 2, 2, 3, 4, 6, 6,
+    /* 0x50 */ 2, 5, 2, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,
+    /* 0x60 */ 6, 6, 2, 8, 3, 3, 5, 5, 4, 2, 2, 2, 5, 4, 6, 6,
+    /* 0x70 */ 2, 5, 2, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,
+    /* 0x80 */ 2, 6, 2, 6, 3, 3, 3, 3, 2, 2, 2, 2, 4, 4, 4, 4,
+    /* 0x90 */ 2, 6, 2, 6, 4, 4, 4, 4, 2, 5, 2, 5, 5, 5, 5, 5,
+    /* 0xA0 */ 2, 6, 2, 6, 3, 3, 3, 3, 2, 2, 2, 0, 4, 4, 4, 4,
+    /* 0xB0 */ 2, 5, 2, 5, 4, 4, 4, 4, 2, 4, 2, 4, 4, 4, 4, 4,
+    /* 0xC0 */ 2, 6, 2, 8, 3, 3, 5, 5, 2, 2, 2, 2, 4, 4, 6, 6,
+    /* 0xD0 */ 2, 5, 2, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,
+    /* 0xE0 */ 2, 6, 2, 8, 3, 3, 5, 5, 2, 2, 2, 2, 4, 4, 6, 6,
+    /* 0xF0 */ 2, 5, 2, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,
+];
+
 #[cfg(test)]
 mod test_addressing_modes {
     use super::super::ram::RAM;
@@ -1352,15 +1721,11 @@ mod test_addressing_modes {
         assert_eq!(addr, Some(0x42));
         assert_eq!(cpu.remain_cycles, 3);
 
-        cpu.remain_cycles = 0;
+        let lda = OpCode(Instruction::LDA, AddressingMode::AbsoluteX, Official);
         cpu.pc = 0x8000;
-        cpu.x = 1;
-        ram[0x8000] = 0x50;
-        ram[0x8001] = 0x81;
-        ram[0x8151] = 0x42;
-        let addr = AddressingMode::AbsoluteX.fetch(&mut cpu, &mut ram);
-        assert_eq!(addr, Some(0x42));
-        assert_eq!(cpu.remain_cycles, 4);
+        assert_eq!(lda.index_penalty(&cpu, &mut ram), 0);
+        ram[0x8000] = 0xFF;
+        assert_eq!(lda.index_penalty(&cpu, &mut ram), 1);
     }
 
     #[test]
@@ -1389,14 +1754,11 @@ mod test_addressing_modes {
         assert_eq!(addr, Some(0x42));
         assert_eq!(cpu.remain_cycles, 3);
 
-        cpu.remain_cycles = 0;
+        let lda = OpCode(Instruction::LDA, AddressingMode::AbsoluteY, Official);
         cpu.pc = 0x8000;
-        ram[0x8000] = 0x50;
-        ram[0x8001] = 0x81;
-        ram[0x8151] = 0x42;
-        let addr = AddressingMode::AbsoluteY.fetch(&mut cpu, &mut ram);
-        assert_eq!(addr, Some(0x42));
-        assert_eq!(cpu.remain_cycles, 4);
+        assert_eq!(lda.index_penalty(&cpu, &mut ram), 0);
+        ram[0x8000] = 0xFF;
+        assert_eq!(lda.index_penalty(&cpu, &mut ram), 1);
     }
 
     #[test]
@@ -1471,16 +1833,12 @@ mod test_addressing_modes {
         assert_eq!(byte, Some(0x42));
         assert_eq!(cpu.remain_cycles, 4);
 
-        cpu.remain_cycles = 0;
         cpu.pc = 0x8000;
         cpu.y = 0x10;
-        ram[0x8000] = 0x01;
         ram[0x01] = 0xF4;
         ram[0x02] = 0x02;
-        ram[0x0304] = 0x42;
-        let byte = AddressingMode::IndirectIndexed.fetch(&mut cpu, &mut ram);
-        assert_eq!(byte, Some(0x42));
-        assert_eq!(cpu.remain_cycles, 5);
+        let lda = OpCode(Instruction::LDA, AddressingMode::IndirectIndexed, Official);
+        assert_eq!(lda.index_penalty(&cpu, &mut ram), 1);
     }
 }
 
@@ -1729,6 +2087,50 @@ mod test_instructions {
         assert_eq!(cpu.flags.r, true);
     }
 
+    #[test]
+    fn test_decimal_flag_round_trip() {
+        let mut cpu = CPU::default();
+        let mut ram = RAM::default();
+        ram.write_rom(
+            0x8000,
+            &[
+                0xF8, // SED
+                0x08, // PHP
+                0xD8, // CLD
+                0x28, // PLP
+            ],
+        );
+        cpu.pc = 0x8000;
+        cpu.sp = 0xFF;
+
+        cpu.step_instruction(&mut ram);
+        cpu.step_instruction(&mut ram);
+        assert_eq!(ram[0x01FF] & 0b00001000, 0b00001000);
+        cpu.step_instruction(&mut ram);
+        assert_eq!(cpu.flags.d, false);
+        cpu.step_instruction(&mut ram);
+        assert_eq!(cpu.flags.d, true);
+    }
+
+    #[test]
+    fn test_decimal_flag_across_interrupt() {
+        let mut cpu = CPU::default();
+        let mut ram = RAM::default();
+        ram.write_rom(0x9000, &[0xD8, 0x40]); // CLD, RTI
+        ram.write_rom(0xFFFE, &[0x00, 0x90]);
+        cpu.pc = 0x8000;
+        cpu.sp = 0xFF;
+        cpu.flags.d = true;
+
+        cpu.interrupt(&mut ram, Interrupt::IRQ);
+        cpu.step_instruction(&mut ram); // the interrupt sequence
+        cpu.step_instruction(&mut ram);
+        assert_eq!(cpu.flags.d, false);
+        cpu.step_instruction(&mut ram);
+        assert_eq!(cpu.pc, 0x8000);
+        assert_eq!(cpu.flags.d, true);
+    }
+
     #[test]
     fn test_and() {
         let mut cpu = CPU::default();
@@ -1802,7 +2204,6 @@ mod test_instructions {
 
     #[test]
     fn test_adc() {
-        // TODO: implement test for v flag
         let mut cpu = CPU::default();
         let mut ram = RAM::default();
 
@@ -1823,6 +2224,34 @@ mod test_instructions {
         assert_eq!(cpu.flags.c, true);
     }
 
+    // Every operand pair and carry-in against a reference computed in wider integers
+    #[test]
+    fn test_adc_exhaustive() {
+        let mut cpu = CPU::default();
+        let mut ram = RAM::default();
+        for a in 0..=0xFFu8 {
+            for m in 0..=0xFFu8 {
+                for &c in [false, true].iter() {
+                    cpu.a = a;
+                    cpu.flags.c = c;
+                    cpu.pc = 0x8000;
+                    ram[0x8000] = m;
+                    OpCode(Instruction::ADC, AddressingMode::Immediate, Official)
+                        .execute(&mut cpu, &mut ram);
+
+                    let sum = a as u16 + m as u16 + c as u16;
+                    let signed = a as i8 as i16 + m as i8 as i16 + c as i16;
+                    let case = (a, m, c);
+                    assert_eq!(cpu.a, sum as u8, "{:?}", case);
+                    assert_eq!(cpu.flags.c, sum > 0xFF, "{:?}", case);
+                    assert_eq!(cpu.flags.z, sum as u8 == 0, "{:?}", case);
+                    assert_eq!(cpu.flags.n, sum & 0x80 != 0, "{:?}", case);
+                    assert_eq!(cpu.flags.v, !(-128..=127).contains(&signed), "{:?}", case);
+                }
+            }
+        }
+    }
+
     #[test]
     fn test_sbc() {
         // TODO: implement test for v flag
@@ -1846,6 +2275,33 @@ mod test_instructions {
         assert_eq!(cpu.flags.c, false);
     }
 
+    #[test]
+    fn test_sbc_exhaustive() {
+        let mut cpu = CPU::default();
+        let mut ram = RAM::default();
+
+        for a in 0..=0xFFu8 {
+            for m in 0..=0xFFu8 {
+                for &c in &[false, true] {
+                    cpu.a = a;
+                    cpu.pc = 0x8000;
+                    cpu.flags.c = c;
+                    ram[0x8000] = m;
+                    OpCode(Instruction::SBC, AddressingMode::Immediate, Official)
+                        .execute(&mut cpu, &mut ram);
+
+                    let expected = a as i16 - m as i16 - (1 - c as i16);
+                    let result = expected as u8;
+                    assert_eq!(cpu.a, result, "{:02X} - {:02X} - !{}", a, m, c);
+                    assert_eq!(cpu.flags.c, expected >= 0, "{:02X} - {:02X} - !{}", a, m, c);
+                    assert_eq!(cpu.flags.z, result == 0);
+                    assert_eq!(cpu.flags.n, result & 0x80 != 0);
+                    assert_eq!(cpu.flags.v, (a ^ m) & (a ^ result) & 0x80 != 0);
+                }
+            }
+        }
+    }
+
     #[test]
     fn test_cmp() {
         let mut cpu = CPU::default();
@@ -2097,25 +2553,65 @@ mod test_instructions {
     }
 
     #[test]
-    fn test_jmp() {
+    fn test_rotate_memory_carry_in() {
         let mut cpu = CPU::default();
         let mut ram = RAM::default();
 
         cpu.pc = 0x8000;
-        ram[0x8000] = 0x02;
-        ram[0x8001] = 0x01;
-        OpCode(Instruction::JMP, AddressingMode::Absolute, Official).execute(&mut cpu, &mut ram);
-        assert_eq!(cpu.pc, 0x0102);
+        cpu.flags.c = true;
+        ram[0x8000] = 0x10;
+        ram[0x10] = 0b00000000;
+        OpCode(Instruction::ROL, AddressingMode::ZeroPage, Official).execute(&mut cpu, &mut ram);
+        assert_eq!(ram[0x10], 0b00000001);
+        assert_eq!(cpu.flags.c, false);
+        assert_eq!(cpu.flags.z, false);
 
         cpu.pc = 0x8000;
-        ram[0x8000] = 0x02;
-        ram[0x8001] = 0x01;
+        cpu.flags.c = true;
+        ram[0x10] = 0b00000000;
+        OpCode(Instruction::ROR, AddressingMode::ZeroPage, Official).execute(&mut cpu, &mut ram);
+        assert_eq!(ram[0x10], 0b10000000);
+        assert_eq!(cpu.flags.c, false);
+        assert_eq!(cpu.flags.n, true);
+    }
+
+    #[test]
+    fn test_jmp() {
+        let mut cpu = CPU::default();
+        let mut ram = RAM::default();
+
+        cpu.pc = 0x8000;
+        ram[0x8000] = 0x02;
+        ram[0x8001] = 0x01;
+        OpCode(Instruction::JMP, AddressingMode::Absolute, Official).execute(&mut cpu, &mut ram);
+        assert_eq!(cpu.pc, 0x0102);
+
+        cpu.pc = 0x8000;
+        ram[0x8000] = 0x02;
+        ram[0x8001] = 0x01;
         ram[0x0102] = 0x04;
         ram[0x0103] = 0x03;
         OpCode(Instruction::JMP, AddressingMode::Indirect, Official).execute(&mut cpu, &mut ram);
         assert_eq!(cpu.pc, 0x0304);
     }
 
+    #[test]
+    fn test_jmp_cycles() {
+        let mut cpu = CPU::default();
+        let mut ram = RAM::default();
+        ram.write_rom(0x8000, &[0x4C, 0x34, 0x12]); // JMP $1234
+        ram.write_rom(0x9000, &[0x6C, 0x34, 0x12]); // JMP ($1234)
+        ram.write_rom(0x1234, &[0x00, 0x80]);
+
+        cpu.pc = 0x8000;
+        assert_eq!(cpu.step_instruction(&mut ram), 3);
+        assert_eq!(cpu.pc, 0x1234);
+
+        cpu.pc = 0x9000;
+        assert_eq!(cpu.step_instruction(&mut ram), 5);
+        assert_eq!(cpu.pc, 0x8000);
+    }
+
     #[test]
     fn test_jsr() {
         let mut cpu = CPU::default();
@@ -2129,6 +2625,16 @@ mod test_instructions {
         assert_eq!(cpu.pc, 0x0102);
         assert_eq!(ram[0x01FF], 0x80);
         assert_eq!(ram[0x01FE], 0x02);
+        assert_eq!(cpu.remain_cycles, 5);
+
+        // with the opcode fetch, 6 cycles
+        cpu.pc = 0x9000;
+        cpu.sp = 0xFF;
+        cpu.remain_cycles = 0;
+        ram.write_rom(0x9000, &[0x20, 0x34, 0x12]); // JSR $1234
+        assert_eq!(cpu.step_instruction(&mut ram), 6);
+        assert_eq!(cpu.pc, 0x1234);
+        assert_eq!(cpu.peek_word(&mut ram, 0x01FE), 0x9002);
     }
 
     #[test]
@@ -2144,6 +2650,26 @@ mod test_instructions {
         assert_eq!(cpu.pc, 0x0103);
     }
 
+    #[test]
+    fn test_jsr_rts_wrap() {
+        let mut cpu = CPU::default();
+        let mut ram = RAM::default();
+
+        // JSR at $FFFD leaves PC at $0000 after its operand
+        cpu.pc = 0xFFFE;
+        cpu.sp = 0xFF;
+        ram[0xFFFE] = 0x00;
+        ram[0xFFFF] = 0x90;
+        OpCode(Instruction::JSR, AddressingMode::Absolute, Official).execute(&mut cpu, &mut ram);
+        assert_eq!(cpu.pc, 0x9000);
+        assert_eq!(ram[0x01FF], 0xFF);
+        assert_eq!(ram[0x01FE], 0xFF);
+
+        OpCode(Instruction::RTS, AddressingMode::Implied, Official).execute(&mut cpu, &mut ram);
+        assert_eq!(cpu.pc, 0x0000);
+        assert_eq!(cpu.sp, 0xFF);
+    }
+
     #[test]
     fn test_bcc() {
         let mut cpu = CPU::default();
@@ -2363,13 +2889,28 @@ mod test_instructions {
         let mut cpu = CPU::default();
         let mut ram = RAM::default();
 
-        cpu.pc = 0x8000;
+        // BRK at 0x8000, its opcode already fetched
+        cpu.pc = 0x8001;
         cpu.sp = 0xFF;
         OpCode(Instruction::BRK, AddressingMode::Implied, Official).execute(&mut cpu, &mut ram);
-        assert_eq!(ram[0x01FE], 0x00);
+        assert_eq!(ram[0x01FE], 0x02);
         assert_eq!(ram[0x01FF], 0x80);
         assert_eq!(ram[0x01FD], 0b00110000);
+        assert_eq!(cpu.flags.get_as_u8(), 0b00100100);
         assert_eq!(cpu.flags.i, true);
+        assert_eq!(cpu.remain_cycles, 6);
+    }
+
+    #[test]
+    fn test_brk_vector() {
+        let mut cpu = CPU::default();
+        let mut ram = RAM::default();
+
+        cpu.pc = 0x8000;
+        ram[0xFFFE] = 0x34;
+        ram[0xFFFF] = 0x12;
+        OpCode(Instruction::BRK, AddressingMode::Implied, Official).execute(&mut cpu, &mut ram);
+        assert_eq!(cpu.pc, 0x1234);
     }
 
     #[test]
@@ -2388,6 +2929,7 @@ mod test_instructions {
         assert_eq!(cpu.flags.i, false);
         assert_eq!(cpu.flags.b, false);
         assert_eq!(cpu.pc, 0x9000);
+        assert_eq!(cpu.remain_cycles, 5);
     }
 
     #[test]
@@ -2521,6 +3063,441 @@ mod test_instructions {
         assert_eq!(cpu.flags.c, false);
     }
 
+    #[test]
+    fn test_anc() {
+        let mut cpu = CPU::default();
+        let mut ram = RAM::default();
+
+        cpu.pc = 0x8000;
+        cpu.a = 0b11000011;
+        ram[0x8000] = 0b10000001;
+        OpCode(Instruction::ANC, AddressingMode::Immediate, Unofficial).execute(&mut cpu, &mut ram);
+        assert_eq!(cpu.a, 0b10000001);
+        assert_eq!(cpu.flags.n, true);
+        assert_eq!(cpu.flags.c, cpu.flags.n);
+
+        cpu.pc = 0x8000;
+        ram[0x8000] = 0b00000001;
+        OpCode(Instruction::ANC, AddressingMode::Immediate, Unofficial).execute(&mut cpu, &mut ram);
+        assert_eq!(cpu.a, 0b00000001);
+        assert_eq!(cpu.flags.n, false);
+        assert_eq!(cpu.flags.c, false);
+    }
+
+    #[test]
+    fn test_alr() {
+        let mut cpu = CPU::default();
+        let mut ram = RAM::default();
+
+        cpu.pc = 0x8000;
+        cpu.a = 0b11110011;
+        ram[0x8000] = 0b10101011;
+        OpCode(Instruction::ALR, AddressingMode::Immediate, Unofficial).execute(&mut cpu, &mut ram);
+        assert_eq!(cpu.a, 0b01010001);
+        assert_eq!(cpu.flags.c, true);
+        assert_eq!(cpu.flags.n, false);
+
+        cpu.pc = 0x8000;
+        ram[0x8000] = 0b11111110;
+        OpCode(Instruction::ALR, AddressingMode::Immediate, Unofficial).execute(&mut cpu, &mut ram);
+        assert_eq!(cpu.a, 0b00101000);
+        assert_eq!(cpu.flags.c, false);
+    }
+
+    #[test]
+    fn test_arr() {
+        let mut cpu = CPU::default();
+        let mut ram = RAM::default();
+
+        cpu.pc = 0x8000;
+        cpu.a = 0xFF;
+        cpu.flags.c = true;
+        ram[0x8000] = 0x80;
+        OpCode(Instruction::ARR, AddressingMode::Immediate, Unofficial).execute(&mut cpu, &mut ram);
+        assert_eq!(cpu.a, 0xC0);
+        assert_eq!(cpu.flags.n, true);
+        assert_eq!(cpu.flags.c, true);
+        assert_eq!(cpu.flags.v, true);
+
+        // bit 6 clear, bit 5 set
+        cpu.pc = 0x8000;
+        cpu.a = 0xFF;
+        cpu.flags.c = false;
+        ram[0x8000] = 0x40;
+        OpCode(Instruction::ARR, AddressingMode::Immediate, Unofficial).execute(&mut cpu, &mut ram);
+        assert_eq!(cpu.a, 0x20);
+        assert_eq!(cpu.flags.n, false);
+        assert_eq!(cpu.flags.c, false);
+        assert_eq!(cpu.flags.v, true);
+
+        // bits 6 and 5 both set
+        cpu.pc = 0x8000;
+        cpu.a = 0xFF;
+        ram[0x8000] = 0xC0;
+        OpCode(Instruction::ARR, AddressingMode::Immediate, Unofficial).execute(&mut cpu, &mut ram);
+        assert_eq!(cpu.a, 0x60);
+        assert_eq!(cpu.flags.c, true);
+        assert_eq!(cpu.flags.v, false);
+    }
+
+    #[test]
+    fn test_ane() {
+        let mut cpu = CPU::default();
+        let mut ram = RAM::default();
+
+        cpu.pc = 0x8000;
+        cpu.a = 0x00;
+        cpu.x = 0xF0;
+        ram[0x8000] = 0x9F;
+        OpCode(Instruction::ANE, AddressingMode::Immediate, Unofficial).execute(&mut cpu, &mut ram);
+        assert_eq!(cpu.a, 0x90);
+        assert_eq!(cpu.flags.n, true);
+
+        cpu.pc = 0x8000;
+        cpu.ane_magic = 0xEE;
+        cpu.a = 0x01;
+        cpu.x = 0xFF;
+        ram[0x8000] = 0x11;
+        OpCode(Instruction::ANE, AddressingMode::Immediate, Unofficial).execute(&mut cpu, &mut ram);
+        assert_eq!(cpu.a, 0x01);
+
+        cpu.pc = 0x8000;
+        cpu.a = 0x00;
+        OpCode(Instruction::ANE, AddressingMode::Immediate, Unofficial).execute(&mut cpu, &mut ram);
+        assert_eq!(cpu.a, 0x00);
+        assert_eq!(cpu.flags.z, true);
+    }
+
+    #[test]
+    fn test_sbx() {
+        let mut cpu = CPU::default();
+        let mut ram = RAM::default();
+
+        cpu.pc = 0x8000;
+        cpu.a = 0xF3;
+        cpu.x = 0x3F;
+        cpu.flags.v = true;
+        ram[0x8000] = 0x03;
+        OpCode(Instruction::SBX, AddressingMode::Immediate, Unofficial).execute(&mut cpu, &mut ram);
+        assert_eq!(cpu.x, 0x30);
+        assert_eq!(cpu.a, 0xF3);
+        assert_eq!(cpu.flags.c, true);
+        assert_eq!(cpu.flags.n, false);
+        assert_eq!(cpu.flags.v, true);
+
+        // borrow
+        cpu.pc = 0x8000;
+        ram[0x8000] = 0x31;
+        OpCode(Instruction::SBX, AddressingMode::Immediate, Unofficial).execute(&mut cpu, &mut ram);
+        assert_eq!(cpu.x, 0xFF);
+        assert_eq!(cpu.flags.c, false);
+        assert_eq!(cpu.flags.n, true);
+        assert_eq!(cpu.flags.v, true);
+    }
+
+    #[test]
+    fn test_sha() {
+        let mut cpu = CPU::default();
+        let mut ram = RAM::default();
+
+        cpu.pc = 0x8000;
+        cpu.a = 0xF7;
+        cpu.x = 0x7F;
+        cpu.y = 0x10;
+        ram[0x8000] = 0x00;
+        ram[0x8001] = 0x12;
+        OpCode(Instruction::SHA, AddressingMode::AbsoluteY, Unofficial).execute(&mut cpu, &mut ram);
+        assert_eq!(ram[0x1210], 0x13);
+        assert_eq!(cpu.remain_cycles, 4);
+
+        // crossing a page replaces the high byte of the target with the stored value
+        cpu.pc = 0x8000;
+        cpu.remain_cycles = 0;
+        cpu.a = 0xFF;
+        cpu.x = 0x07;
+        cpu.y = 0x20;
+        ram[0x8000] = 0x40;
+        ram[0x40] = 0xF0;
+        ram[0x41] = 0x12;
+        OpCode(
+            Instruction::SHA,
+            AddressingMode::IndirectIndexed,
+            Unofficial,
+        )
+        .execute(&mut cpu, &mut ram);
+        assert_eq!(ram[0x1310], 0x00);
+        assert_eq!(ram[0x0310], 0x03);
+        assert_eq!(cpu.remain_cycles, 5);
+    }
+
+    #[test]
+    fn test_shx() {
+        let mut cpu = CPU::default();
+        let mut ram = RAM::default();
+
+        cpu.pc = 0x8000;
+        cpu.x = 0xFF;
+        cpu.y = 0x10;
+        ram[0x8000] = 0x00;
+        ram[0x8001] = 0x12;
+        OpCode(Instruction::SHX, AddressingMode::AbsoluteY, Unofficial).execute(&mut cpu, &mut ram);
+        assert_eq!(ram[0x1210], 0x13);
+        assert_eq!(cpu.remain_cycles, 4);
+
+        // crossing a page replaces the high byte of the target with the stored value
+        cpu.pc = 0x8000;
+        cpu.x = 0x05;
+        cpu.y = 0x20;
+        ram[0x8000] = 0xF0;
+        ram[0x8001] = 0x12;
+        OpCode(Instruction::SHX, AddressingMode::AbsoluteY, Unofficial).execute(&mut cpu, &mut ram);
+        assert_eq!(ram[0x1310], 0x00);
+        assert_eq!(ram[0x0110], 0x01);
+    }
+
+    #[test]
+    fn test_shy() {
+        let mut cpu = CPU::default();
+        let mut ram = RAM::default();
+
+        cpu.pc = 0x8000;
+        cpu.x = 0x10;
+        cpu.y = 0xFF;
+        ram[0x8000] = 0x00;
+        ram[0x8001] = 0x12;
+        OpCode(Instruction::SHY, AddressingMode::AbsoluteX, Unofficial).execute(&mut cpu, &mut ram);
+        assert_eq!(ram[0x1210], 0x13);
+        assert_eq!(cpu.remain_cycles, 4);
+
+        // crossing a page replaces the high byte of the target with the stored value
+        cpu.pc = 0x8000;
+        cpu.x = 0x20;
+        cpu.y = 0x05;
+        ram[0x8000] = 0xF0;
+        ram[0x8001] = 0x12;
+        OpCode(Instruction::SHY, AddressingMode::AbsoluteX, Unofficial).execute(&mut cpu, &mut ram);
+        assert_eq!(ram[0x1310], 0x00);
+        assert_eq!(ram[0x0110], 0x01);
+    }
+
+    #[test]
+    fn test_tas() {
+        let mut cpu = CPU::default();
+        let mut ram = RAM::default();
+
+        cpu.pc = 0x8000;
+        cpu.a = 0xF3;
+        cpu.x = 0x3F;
+        cpu.y = 0x10;
+        ram[0x8000] = 0x00;
+        ram[0x8001] = 0x12;
+        OpCode(Instruction::TAS, AddressingMode::AbsoluteY, Unofficial).execute(&mut cpu, &mut ram);
+        assert_eq!(cpu.sp, 0x33);
+        assert_eq!(cpu.a, 0xF3);
+        assert_eq!(cpu.x, 0x3F);
+        assert_eq!(ram[0x1210], 0x13);
+    }
+
+    #[test]
+    fn test_las() {
+        let mut cpu = CPU::default();
+        let mut ram = RAM::default();
+
+        cpu.pc = 0x8000;
+        cpu.sp = 0xF0;
+        cpu.y = 0x10;
+        ram[0x8000] = 0x00;
+        ram[0x8001] = 0x12;
+        ram[0x1210] = 0x9F;
+        OpCode(Instruction::LAS, AddressingMode::AbsoluteY, Unofficial).execute(&mut cpu, &mut ram);
+        assert_eq!(cpu.a, 0x90);
+        assert_eq!(cpu.x, 0x90);
+        assert_eq!(cpu.sp, 0x90);
+        assert_eq!(cpu.flags.n, true);
+        assert_eq!(cpu.remain_cycles, 3);
+
+        // page crossed
+        cpu.pc = 0x8000;
+        cpu.remain_cycles = 0;
+        cpu.y = 0x20;
+        ram[0x8000] = 0xF0;
+        ram[0x1310] = 0x0F;
+        OpCode(Instruction::LAS, AddressingMode::AbsoluteY, Unofficial).execute(&mut cpu, &mut ram);
+        assert_eq!(cpu.a, 0x00);
+        assert_eq!(cpu.x, 0x00);
+        assert_eq!(cpu.sp, 0x00);
+        assert_eq!(cpu.flags.z, true);
+        assert_eq!(cpu.remain_cycles, 4);
+    }
+
+    // Records the address of every bus access.
+    struct AccessLog {
+        ram: RAM,
+        reads: Vec<usize>,
+        writes: Vec<usize>,
+    }
+    impl MemIO for AccessLog {
+        fn read_byte(&mut self, address: usize) -> u8 {
+            self.reads.push(address);
+            self.ram[address]
+        }
+        fn read_byte_without_effect(&mut self, address: usize) -> u8 {
+            self.ram[address]
+        }
+        fn write_byte(&mut self, address: usize, byte: u8) {
+            self.writes.push(address);
+            self.ram[address] = byte;
+        }
+    }
+
+    #[test]
+    fn test_indexed_store_dummy_read() {
+        let mut cpu = CPU::default();
+        let mut mem = AccessLog {
+            ram: RAM::default(),
+            reads: vec![],
+            writes: vec![],
+        };
+
+        cpu.pc = 0x0200;
+        cpu.a = 0x42;
+        cpu.x = 0x01;
+        mem.ram[0x0200] = 0xFF;
+        mem.ram[0x0201] = 0x80;
+        OpCode(Instruction::STA, AddressingMode::AbsoluteX, Official).execute(&mut cpu, &mut mem);
+        assert_eq!(mem.reads, vec![0x0200, 0x0201, 0x8000]);
+        assert_eq!(mem.writes, vec![0x8100]);
+        assert_eq!(mem.ram[0x8100], 0x42);
+    }
+
+    #[test]
+    fn test_branch_dummy_fetch() {
+        let mut cpu = CPU::default();
+        let mut mem = AccessLog {
+            ram: RAM::default(),
+            reads: vec![],
+            writes: vec![],
+        };
+        mem.ram.write_rom(0x0200, &[0xD0, 0x02]); // BNE +2
+        cpu.pc = 0x0200;
+        assert_eq!(cpu.step_instruction(&mut mem), 3);
+        assert_eq!(mem.reads, vec![0x0200, 0x0201, 0x0202]);
+        assert_eq!(cpu.pc, 0x0204);
+
+        // crossing a page reads the target before PCH is fixed up
+        mem.reads.clear();
+        mem.ram.write_rom(0x02FD, &[0xD0, 0x10]); // BNE +16
+        cpu.pc = 0x02FD;
+        assert_eq!(cpu.step_instruction(&mut mem), 4);
+        assert_eq!(mem.reads, vec![0x02FD, 0x02FE, 0x02FF, 0x020F]);
+        assert_eq!(cpu.pc, 0x030F);
+    }
+
+    // 2 cycles when not taken, 3 when taken, 4 when taken across a page
+    #[test]
+    fn test_branch_cycles() {
+        for &op in &[0x10u8, 0x30, 0x50, 0x70, 0x90, 0xB0, 0xD0, 0xF0] {
+            // bit 5 of the opcode picks the flag value that is branched on
+            let taken_on = op & 0x20 != 0;
+            for &(pc, offset, taken, cycles) in &[
+                (0x0200, 0x02, false, 2),
+                (0x0200, 0x02, true, 3),
+                (0x02FD, 0x10, true, 4),
+                (0x0300, 0xF0, true, 4),
+            ] {
+                let mut cpu = CPU::default();
+                let mut ram = RAM::default();
+                ram.write_rom(pc, &[op, offset]);
+                cpu.pc = pc as u16;
+                let flag = taken_on == taken;
+                cpu.flags.n = flag;
+                cpu.flags.v = flag;
+                cpu.flags.c = flag;
+                cpu.flags.z = flag;
+                assert_eq!(
+                    cpu.step_instruction(&mut ram),
+                    cycles,
+                    "{:#04X} at {:#06X}",
+                    op,
+                    pc
+                );
+                assert_eq!(cpu.total_cycles, cycles);
+            }
+        }
+    }
+
+    // The penalty depends on the base address and the indexed one, not on where PC is.
+    #[test]
+    fn test_absolute_indexed_page_cross() {
+        let mut cpu = CPU::default();
+        let mut ram = RAM::default();
+        ram.write_rom(
+            0x12F0,
+            &[
+                0xBD, 0xF8, 0x12, // LDA $12F8,X
+                0xBD, 0x00, 0x13, // LDA $1300,X
+                0xB9, 0xF8, 0x12, // LDA $12F8,Y
+                0xB9, 0x00, 0x13, // LDA $1300,Y
+            ],
+        );
+        cpu.pc = 0x12F0;
+        cpu.x = 0x10;
+        cpu.y = 0x10;
+        assert_eq!(cpu.step_instruction(&mut ram), 5);
+        assert_eq!(cpu.step_instruction(&mut ram), 4);
+        assert_eq!(cpu.step_instruction(&mut ram), 5);
+        assert_eq!(cpu.step_instruction(&mut ram), 4);
+    }
+
+    #[test]
+    fn test_index_penalty_read_vs_write() {
+        let mut cpu = CPU::default();
+        let mut ram = RAM::default();
+        ram.write_rom(
+            0x0200,
+            &[
+                0xBD, 0xFF, 0x80, // LDA $80FF,X
+                0xBD, 0x00, 0x80, // LDA $8000,X
+                0x9D, 0xFF, 0x80, // STA $80FF,X
+                0x9D, 0x00, 0x80, // STA $8000,X
+            ],
+        );
+        cpu.pc = 0x0200;
+        cpu.x = 0x01;
+        assert_eq!(cpu.step_instruction(&mut ram), 5);
+        assert_eq!(cpu.step_instruction(&mut ram), 4);
+        assert_eq!(cpu.step_instruction(&mut ram), 5);
+        assert_eq!(cpu.step_instruction(&mut ram), 5);
+    }
+
+    // Indexed stores take the fix-up cycle whether or not a page is crossed.
+    #[test]
+    fn test_indexed_store_cycles() {
+        let mut cpu = CPU::default();
+        let mut ram = RAM::default();
+        ram.write_rom(
+            0x8000,
+            &[
+                0x9D, 0xFF, 0x12, // STA $12FF,X
+                0x9D, 0x00, 0x12, // STA $1200,X
+                0x99, 0xFF, 0x12, // STA $12FF,Y
+                0x99, 0x00, 0x12, // STA $1200,Y
+                0x91, 0x10, //       STA ($10),Y
+                0x91, 0x12, //       STA ($12),Y
+            ],
+        );
+        ram.write_rom(0x10, &[0xFF, 0x12, 0x00, 0x12]);
+        cpu.pc = 0x8000;
+        cpu.x = 0x01;
+        cpu.y = 0x01;
+        assert_eq!(cpu.step_instruction(&mut ram), 5);
+        assert_eq!(cpu.step_instruction(&mut ram), 5);
+        assert_eq!(cpu.step_instruction(&mut ram), 5);
+        assert_eq!(cpu.step_instruction(&mut ram), 5);
+        assert_eq!(cpu.step_instruction(&mut ram), 6);
+        assert_eq!(cpu.step_instruction(&mut ram), 6);
+    }
+
     #[test]
     fn test_skb() {
         let mut cpu = CPU::default();
@@ -2547,3 +3524,267 @@ mod test_instructions {
         assert_eq!(cpu.remain_cycles, 3);
     }
 }
+
+#[cfg(test)]
+mod test_opcodes {
+    use super::super::ram::RAM;
+    use super::*;
+
+    #[test]
+    fn test_base_cycles() {
+        let lda = |mode| OpCode(Instruction::LDA, mode, Official).base_cycles();
+        assert_eq!(lda(AddressingMode::Immediate), 2);
+        assert_eq!(lda(AddressingMode::ZeroPage), 3);
+        assert_eq!(lda(AddressingMode::Absolute), 4);
+        assert_eq!(lda(AddressingMode::AbsoluteX), 4);
+        assert_eq!(
+            OpCode(Instruction::LDA, AddressingMode::IndirectZeroPage, Official).base_cycles(),
+            5
+        );
+
+        assert_eq!(base_cycles(0xBD), 4); // LDA abs,X
+        assert_eq!(base_cycles(0x20), 6); // JSR
+        assert_eq!(base_cycles(0xAB), 0); // undefined
+    }
+
+    // Every opcode takes its base cycles, plus one for an indexed read crossing a page,
+    // and one for a branch taken, or two if it lands on another page.
+    #[test]
+    fn test_base_cycles_match_execution() {
+        // (index, operand byte, branch taken, extra cycles for a read and for a branch)
+        let cases = [
+            (0x01, 0x10, false, 0, 0),
+            (0xFF, 0x10, false, 1, 0),
+            (0x01, 0x10, true, 0, 1),
+            (0x01, 0xF0, true, 0, 2),
+        ];
+        for (byte, op) in OPCODES.iter().enumerate() {
+            let op = match op {
+                Some(op) => op,
+                None => continue,
+            };
+            let is_branch = op.1 == AddressingMode::Relative;
+            let is_indexed_read = op.memory_access() == MemAccess::Read
+                && matches!(
+                    op.1,
+                    AddressingMode::AbsoluteX
+                        | AddressingMode::AbsoluteY
+                        | AddressingMode::IndirectIndexed
+                );
+            for &(index, operand, taken, read_extra, branch_extra) in cases.iter() {
+                let mut cpu = CPU::default();
+                let mut ram = RAM::default();
+                // abs is $2010 and (zp),Y points at $3080, so an index of $FF crosses a page
+                ram.write_rom(0x8000, &[byte as u8, operand, 0x20]);
+                ram.write_rom(0x10, &[0x80, 0x30]);
+                cpu.pc = 0x8000;
+                cpu.x = index;
+                cpu.y = index;
+                // branches to $8012 for $10 and to $7FF2 for $F0
+                cpu.flags.c = (op.0 == Instruction::BCS) == taken;
+                cpu.flags.z = (op.0 == Instruction::BEQ) == taken;
+                cpu.flags.n = (op.0 == Instruction::BMI) == taken;
+                cpu.flags.v = (op.0 == Instruction::BVS) == taken;
+                let extra = if is_indexed_read { read_extra } else { 0 }
+                    + if is_branch { branch_extra } else { 0 };
+                assert_eq!(
+                    cpu.step_instruction(&mut ram),
+                    op.base_cycles() as usize + extra,
+                    "{:#04X} {} index {:#04X} operand {:#04X} taken {}",
+                    byte,
+                    op,
+                    index,
+                    operand,
+                    taken
+                );
+            }
+        }
+    }
+
+    // Every official opcode as "mnemonic: byte mode; ...", written out independently of OPCODES
+    const OFFICIAL: &str = "
+        ADC: 69 #imm; 65 zp; 75 zp,X; 6D abs; 7D abs,X; 79 abs,Y; 61 (zp,X); 71 (zp),Y
+        AND: 29 #imm; 25 zp; 35 zp,X; 2D abs; 3D abs,X; 39 abs,Y; 21 (zp,X); 31 (zp),Y
+        ASL: 0A A; 06 zp; 16 zp,X; 0E abs; 1E abs,X
+        BCC: 90 rel
+        BCS: B0 rel
+        BEQ: F0 rel
+        BIT: 24 zp; 2C abs
+        BMI: 30 rel
+        BNE: D0 rel
+        BPL: 10 rel
+        BRK: 00
+        BVC: 50 rel
+        BVS: 70 rel
+        CLC: 18
+        CLD: D8
+        CLI: 58
+        CLV: B8
+        CMP: C9 #imm; C5 zp; D5 zp,X; CD abs; DD abs,X; D9 abs,Y; C1 (zp,X); D1 (zp),Y
+        CPX: E0 #imm; E4 zp; EC abs
+        CPY: C0 #imm; C4 zp; CC abs
+        DEC: C6 zp; D6 zp,X; CE abs; DE abs,X
+        DEX: CA
+        DEY: 88
+        EOR: 49 #imm; 45 zp; 55 zp,X; 4D abs; 5D abs,X; 59 abs,Y; 41 (zp,X); 51 (zp),Y
+        INC: E6 zp; F6 zp,X; EE abs; FE abs,X
+        INX: E8
+        INY: C8
+        JMP: 4C abs; 6C (abs)
+        JSR: 20 abs
+        LDA: A9 #imm; A5 zp; B5 zp,X; AD abs; BD abs,X; B9 abs,Y; A1 (zp,X); B1 (zp),Y
+        LDX: A2 #imm; A6 zp; B6 zp,Y; AE abs; BE abs,Y
+        LDY: A0 #imm; A4 zp; B4 zp,X; AC abs; BC abs,X
+        LSR: 4A A; 46 zp; 56 zp,X; 4E abs; 5E abs,X
+        NOP: EA
+        ORA: 09 #imm; 05 zp; 15 zp,X; 0D abs; 1D abs,X; 19 abs,Y; 01 (zp,X); 11 (zp),Y
+        PHA: 48
+        PHP: 08
+        PLA: 68
+        PLP: 28
+        ROL: 2A A; 26 zp; 36 zp,X; 2E abs; 3E abs,X
+        ROR: 6A A; 66 zp; 76 zp,X; 6E abs; 7E abs,X
+        RTI: 40
+        RTS: 60
+        SBC: E9 #imm; E5 zp; F5 zp,X; ED abs; FD abs,X; F9 abs,Y; E1 (zp,X); F1 (zp),Y
+        SEC: 38
+        SED: F8
+        SEI: 78
+        STA: 85 zp; 95 zp,X; 8D abs; 9D abs,X; 99 abs,Y; 81 (zp,X); 91 (zp),Y
+        STX: 86 zp; 96 zp,Y; 8E abs
+        STY: 84 zp; 94 zp,X; 8C abs
+        TAX: AA
+        TAY: A8
+        TSX: BA
+        TXA: 8A
+        TXS: 9A
+        TYA: 98
+    ";
+
+    #[test]
+    fn test_official_opcodes() {
+        let mut count = 0;
+        for line in OFFICIAL
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+        {
+            let (mnemonic, entries) = line.split_once(": ").unwrap();
+            for entry in entries.split("; ") {
+                let (byte, mode) = entry.split_once(' ').unwrap_or((entry, ""));
+                let byte = u8::from_str_radix(byte, 16).unwrap();
+                let expected = format!("{} {}", mnemonic, mode);
+                let op = OPCODES[byte as usize].expect(&expected);
+                assert_eq!(op.to_string(), expected.trim_end(), "{:#04X}", byte);
+                assert_eq!(op.is_official(), true, "{:#04X}", byte);
+                count += 1;
+            }
+        }
+        assert_eq!(count, 151);
+        let official = OPCODES.iter().flatten().filter(|op| op.is_official());
+        assert_eq!(official.count(), count);
+    }
+
+    // Each opcode and operand byte is fetched exactly once.
+    #[test]
+    fn test_operand_fetched_once() {
+        let cmos = [0x04, 0x0C, 0x12, 0x14, 0x1C]
+            .iter()
+            .map(|&b| (b, cmos_opcode(b)));
+        let nmos = (0..=0xFFu8).map(|b| (b, OPCODES[b as usize]));
+        for (byte, cmos, op) in nmos
+            .map(|(b, op)| (b, false, op))
+            .chain(cmos.map(|(b, op)| (b, true, op)))
+        {
+            let op = match op {
+                Some(op) => op,
+                None => continue,
+            };
+            let mut cpu = CPU::default();
+            let mut ram = RAM::default();
+            ram.write_rom(0x8000, &[byte, 0x10, 0x20]);
+            cpu.pc = 0x8000;
+            cpu.cmos = cmos;
+            cpu.capture_bus = true;
+
+            cpu.step_instruction(&mut ram);
+            let len = 1 + op.1.operand_len() as u16;
+            for addr in 0x8000..0x8000 + len {
+                let fetches = cpu
+                    .bus_cycles()
+                    .iter()
+                    .filter(|cycle| cycle.addr == addr && !cycle.is_write)
+                    .count();
+                assert_eq!(fetches, 1, "{:#04X} {} at {:#06X}", byte, op, addr);
+            }
+        }
+    }
+
+    #[test]
+    fn test_opcode_info() {
+        assert_eq!(
+            opcode_info(0xA9),
+            OpcodeInfo::Official(Instruction::LDA, AddressingMode::Immediate)
+        );
+        assert_eq!(
+            opcode_info(0xA7),
+            OpcodeInfo::Unofficial(Instruction::LAX, AddressingMode::ZeroPage)
+        );
+        assert_eq!(opcode_info(0xAB), OpcodeInfo::Undefined);
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(OPCODES[0xA1].unwrap().to_string(), "LDA (zp,X)");
+        assert_eq!(OPCODES[0x0A].unwrap().to_string(), "ASL A");
+        assert_eq!(OPCODES[0xB1].unwrap().to_string(), "LDA (zp),Y");
+        assert_eq!(OPCODES[0x6C].unwrap().to_string(), "JMP (abs)");
+        assert_eq!(OPCODES[0xEA].unwrap().to_string(), "NOP");
+        assert_eq!(OPCODES[0x04].unwrap().to_string(), "NOP zp");
+    }
+
+    #[test]
+    fn test_memory_access() {
+        assert_eq!(OPCODES[0xA9].unwrap().memory_access(), MemAccess::Read); // LDA #
+        assert_eq!(OPCODES[0xBD].unwrap().memory_access(), MemAccess::Read); // LDA abs,X
+        assert_eq!(OPCODES[0x85].unwrap().memory_access(), MemAccess::Write); // STA zp
+        assert_eq!(
+            OPCODES[0xEE].unwrap().memory_access(),
+            MemAccess::ReadModifyWrite
+        ); // INC abs
+        assert_eq!(
+            OPCODES[0x0E].unwrap().memory_access(),
+            MemAccess::ReadModifyWrite
+        ); // ASL abs
+        assert_eq!(OPCODES[0x0A].unwrap().memory_access(), MemAccess::None); // ASL A
+        assert_eq!(OPCODES[0xE8].unwrap().memory_access(), MemAccess::None); // INX
+        assert_eq!(OPCODES[0x4C].unwrap().memory_access(), MemAccess::None); // JMP abs
+    }
+
+    #[test]
+    fn test_is_control_flow() {
+        assert_eq!(JMP.is_control_flow(), true);
+        assert_eq!(BNE.is_control_flow(), true);
+        assert_eq!(RTS.is_control_flow(), true);
+        assert_eq!(LDA.is_control_flow(), false);
+        assert_eq!(INX.is_control_flow(), false);
+    }
+
+    #[test]
+    fn test_predict_page_cross() {
+        let mut cpu = CPU::default();
+        let mut ram = RAM::default();
+        ram.write_rom(0x8000, &[0xBD, 0xF0, 0x12]); // LDA $12F0,X
+        cpu.pc = 0x8000;
+
+        assert_eq!(AbsoluteX.is_indexed(), true);
+        assert_eq!(Absolute.is_indexed(), false);
+
+        cpu.x = 0x0F;
+        assert_eq!(AbsoluteX.predict_page_cross(&cpu, &mut ram), false);
+        cpu.x = 0x10;
+        assert_eq!(AbsoluteX.predict_page_cross(&cpu, &mut ram), true);
+        assert_eq!(cpu.pc, 0x8000);
+        assert_eq!(cpu.remain_cycles, 0);
+    }
+}
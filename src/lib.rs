@@ -1,4 +1,13 @@
+#![cfg_attr(
+    test,
+    allow(clippy::bool_assert_comparison, clippy::field_reassign_with_default)
+)]
+
+pub mod bus;
 pub mod cpu;
+pub mod disasm;
 pub mod instruction;
 pub mod ram;
 pub mod reset;
+pub mod system;
+pub mod testing;
@@ -1,10 +1,5 @@
-mod cpu;
-mod instruction;
-mod ram;
-mod reset;
-
-use cpu::CPU;
-use ram::RAM;
+use emu6502::cpu::CPU;
+use emu6502::ram::RAM;
 
 fn main() {
     let mut cpu = CPU::default();
@@ -17,7 +12,7 @@ fn main() {
     ram[0xFFFD] = 0x80;
 
     ram[0x42] = 0x84;
-    cpu.execute(4, &mut ram);
+    cpu.execute(9, &mut ram);
     println!("CPU: {:?}", cpu);
 }
 
@@ -25,9 +20,6 @@ fn main() {
 mod tests {
     use super::*;
 
-    use cpu::CPU;
-    use ram::RAM;
-
     #[test]
     fn test_case1() {
         let mut cpu = CPU::default();
@@ -47,7 +39,7 @@ mod tests {
 
         ram[0x42] = 0x84;
 
-        cpu.execute(15, &mut ram);
+        cpu.execute(18, &mut ram);
         assert_eq!(cpu.a, 0x84);
         assert_eq!(cpu.x, 0x02);
         assert_eq!(cpu.y, 0x80);
@@ -90,7 +82,7 @@ mod tests {
         ram[0xFFFC] = 0x00;
         ram[0xFFFD] = 0x80;
 
-        let cycles = 93;
+        let cycles = 96;
         cpu.execute(cycles, &mut ram);
         assert_eq!(cpu.a, 0x0D);
     }
@@ -121,7 +113,7 @@ mod tests {
         ram[0xFFFC] = 0x03;
         ram[0xFFFD] = 0x80;
 
-        let cycles = 18;
+        let cycles = 21;
         cpu.execute(cycles, &mut ram);
         assert_eq!(cpu.a, 0x42);
     }
@@ -1,17 +1,50 @@
+use std::collections::BTreeSet;
 use std::ops::{Index, IndexMut};
 
 use crate::reset::Reset;
+use crate::testing::Rng;
 
 pub trait MemIO {
     fn read_byte(&mut self, address: usize) -> u8;
     fn read_byte_without_effect(&mut self, address: usize) -> u8;
     fn write_byte(&mut self, address: usize, byte: u8);
+
+    // Fallible accesses, for memory that can report errors. Plain memory never fails.
+    fn try_read_byte(&mut self, address: usize) -> Result<u8, MemError> {
+        Ok(self.read_byte(address))
+    }
+
+    fn try_write_byte(&mut self, address: usize, byte: u8) -> Result<(), MemError> {
+        self.write_byte(address, byte);
+        Ok(())
+    }
+
+    // True for addresses known to have never been written, for memory that tracks it.
+    fn is_uninitialized(&self, _address: usize) -> bool {
+        false
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum MemError {
+    Fault(usize),
 }
 
+impl std::fmt::Display for MemError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MemError::Fault(address) => write!(f, "memory fault at {:04X}", address),
+        }
+    }
+}
+
+impl std::error::Error for MemError {}
+
 const MAX_MEMORY: usize = 0x100 * 0x100;
 #[derive(Debug)]
 pub struct RAM {
     inner: Vec<u8>,
+    written: Option<Vec<u64>>, // bitmap of written addresses, while tracking
 }
 
 impl Index<usize> for RAM {
@@ -23,6 +56,7 @@ impl Index<usize> for RAM {
 
 impl IndexMut<usize> for RAM {
     fn index_mut(&mut self, index: usize) -> &mut Self::Output {
+        self.mark_written(index);
         &mut self.inner[index]
     }
 }
@@ -31,6 +65,7 @@ impl Default for RAM {
     fn default() -> Self {
         RAM {
             inner: vec![0; MAX_MEMORY],
+            written: None,
         }
     }
 }
@@ -38,15 +73,185 @@ impl Default for RAM {
 impl RAM {
     #[allow(dead_code)]
     pub fn new(buf: Vec<u8>) -> Self {
-        Self { inner: buf }
+        Self {
+            inner: buf,
+            written: None,
+        }
     }
 
     #[allow(dead_code)]
     pub fn write_rom(&mut self, start_address: usize, data: &[u8]) {
         self.inner[start_address..(start_address + data.len())].clone_from_slice(data);
+        for address in start_address..start_address + data.len() {
+            self.mark_written(address);
+        }
+    }
+
+    // Copies only the part of `data` that fits below the top of memory and returns its length.
+    pub fn load_rom_truncating(&mut self, start_address: usize, data: &[u8]) -> usize {
+        if start_address >= self.inner.len() {
+            return 0;
+        }
+        let len = data.len().min(self.inner.len() - start_address);
+        self.write_rom(start_address, &data[..len]);
+        len
+    }
+
+    // Copies `data`, continuing from address 0 past the top of memory.
+    pub fn load_rom_wrapping(&mut self, start_address: usize, data: &[u8]) {
+        for (i, &byte) in data.iter().enumerate() {
+            let address = (start_address + i) % self.inner.len();
+            self.mark_written(address);
+            self.inner[address] = byte;
+        }
+    }
+
+    // From now on, addresses count as uninitialized until something writes them.
+    pub fn track_uninitialized(&mut self) {
+        self.written = Some(vec![0; self.inner.len().div_ceil(64)]);
+    }
+
+    // Fills memory with pseudo-random bytes, like RAM at power-on. Vectors included, so
+    // load programs afterwards. Doesn't count as writing for `track_uninitialized`.
+    pub fn randomize(&mut self, seed: u64) {
+        let mut rng = Rng::new(seed);
+        for byte in self.inner.iter_mut() {
+            *byte = rng.next_u8();
+        }
+    }
+
+    // Puts back a byte and whether it counted as uninitialized, as undo does. Not a write.
+    pub(crate) fn restore_byte(&mut self, address: usize, byte: u8, uninitialized: bool) {
+        self.inner[address] = byte;
+        if let Some(written) = &mut self.written {
+            if uninitialized {
+                written[address / 64] &= !(1 << (address % 64));
+            } else {
+                written[address / 64] |= 1 << (address % 64);
+            }
+        }
+    }
+
+    fn mark_written(&mut self, address: usize) {
+        if let Some(written) = &mut self.written {
+            written[address / 64] |= 1 << (address % 64);
+        }
+    }
+
+    pub fn set_nmi_vector(&mut self, addr: u16) {
+        self.write_rom(0xFFFA, &addr.to_le_bytes());
+    }
+
+    pub fn set_reset_vector(&mut self, addr: u16) {
+        self.write_rom(0xFFFC, &addr.to_le_bytes());
+    }
+
+    pub fn set_irq_vector(&mut self, addr: u16) {
+        self.write_rom(0xFFFE, &addr.to_le_bytes());
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        &self.inner
+    }
+
+    // Runs of bytes that differ in `other`, as (start address, values in `other`).
+    // Meant to be called as `before.changed_ranges(&after)`.
+    pub fn changed_ranges(&self, other: &RAM) -> Vec<(usize, Vec<u8>)> {
+        let mut ranges: Vec<(usize, Vec<u8>)> = vec![];
+        for (address, (&old, &new)) in self.inner.iter().zip(other.inner.iter()).enumerate() {
+            if old == new {
+                continue;
+            }
+            match ranges.last_mut() {
+                Some((start, bytes)) if *start + bytes.len() == address => bytes.push(new),
+                _ => ranges.push((address, vec![new])),
+            }
+        }
+        ranges
+    }
+
+    // Loads the data records of an Intel HEX image. Start address records are ignored.
+    pub fn load_ihex(&mut self, data: &str) -> Result<(), IhexError> {
+        let mut base = 0;
+        for (i, line) in data.lines().enumerate() {
+            let line_no = i + 1;
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let hex = line
+                .strip_prefix(':')
+                .ok_or(IhexError::MissingStartCode(line_no))?;
+            if hex.len() % 2 != 0 || !hex.is_ascii() {
+                return Err(IhexError::InvalidHex(line_no));
+            }
+            let bytes = (0..hex.len())
+                .step_by(2)
+                .map(|j| u8::from_str_radix(&hex[j..j + 2], 16))
+                .collect::<Result<Vec<u8>, _>>()
+                .map_err(|_| IhexError::InvalidHex(line_no))?;
+            if bytes.len() < 5 || bytes.len() != bytes[0] as usize + 5 {
+                return Err(IhexError::BadLength(line_no));
+            }
+            if bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) != 0 {
+                return Err(IhexError::ChecksumMismatch(line_no));
+            }
+
+            let offset = ((bytes[1] as usize) << 8) + bytes[2] as usize;
+            let record = &bytes[4..bytes.len() - 1];
+            match bytes[3] {
+                0x00 => {
+                    let address = base + offset;
+                    if address + record.len() > self.inner.len() {
+                        return Err(IhexError::AddressOutOfRange(line_no, address));
+                    }
+                    self.write_rom(address, record);
+                }
+                0x01 => return Ok(()),
+                0x02 if record.len() == 2 => {
+                    base = (((record[0] as usize) << 8) + record[1] as usize) << 4;
+                }
+                0x04 if record.len() == 2 => {
+                    base = (((record[0] as usize) << 8) + record[1] as usize) << 16;
+                }
+                0x03 | 0x05 => {}
+                kind => return Err(IhexError::UnsupportedRecord(line_no, kind)),
+            }
+        }
+        Ok(())
     }
 }
 
+// Errors from `RAM::load_ihex`, each carrying the 1-based line number
+#[derive(Debug, PartialEq, Eq)]
+pub enum IhexError {
+    MissingStartCode(usize),
+    InvalidHex(usize),
+    BadLength(usize),
+    ChecksumMismatch(usize),
+    UnsupportedRecord(usize, u8),
+    AddressOutOfRange(usize, usize),
+}
+
+impl std::fmt::Display for IhexError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IhexError::MissingStartCode(line) => write!(f, "line {}: missing ':'", line),
+            IhexError::InvalidHex(line) => write!(f, "line {}: invalid hex digits", line),
+            IhexError::BadLength(line) => write!(f, "line {}: bad record length", line),
+            IhexError::ChecksumMismatch(line) => write!(f, "line {}: checksum mismatch", line),
+            IhexError::UnsupportedRecord(line, kind) => {
+                write!(f, "line {}: unsupported record type {:02X}", line, kind)
+            }
+            IhexError::AddressOutOfRange(line, address) => {
+                write!(f, "line {}: address {:X} is out of range", line, address)
+            }
+        }
+    }
+}
+
+impl std::error::Error for IhexError {}
+
 impl MemIO for RAM {
     fn read_byte(&mut self, address: usize) -> u8 {
         self.inner[address]
@@ -57,17 +262,288 @@ impl MemIO for RAM {
     }
 
     fn write_byte(&mut self, address: usize, byte: u8) {
+        self.mark_written(address);
         self.inner[address] = byte;
     }
+
+    fn is_uninitialized(&self, address: usize) -> bool {
+        match &self.written {
+            Some(written) => written[address / 64] >> (address % 64) & 1 == 0,
+            None => false,
+        }
+    }
 }
 
+// Real hardware does not clear RAM on reset, so its contents persist across
+// `CPU::reset`. Memory-mapped devices that do get cleared should do it here.
 impl Reset for RAM {
     fn reset(&mut self) {}
 }
 
+// Memory backed by a pair of closures, for scripting small memory maps inline.
+pub struct ClosureMem<R, W>
+where
+    R: FnMut(usize) -> u8,
+    W: FnMut(usize, u8),
+{
+    read: R,
+    write: W,
+}
+
+impl<R, W> ClosureMem<R, W>
+where
+    R: FnMut(usize) -> u8,
+    W: FnMut(usize, u8),
+{
+    pub fn new(read: R, write: W) -> Self {
+        Self { read, write }
+    }
+}
+
+impl<R, W> MemIO for ClosureMem<R, W>
+where
+    R: FnMut(usize) -> u8,
+    W: FnMut(usize, u8),
+{
+    fn read_byte(&mut self, address: usize) -> u8 {
+        (self.read)(address)
+    }
+
+    fn read_byte_without_effect(&mut self, address: usize) -> u8 {
+        (self.read)(address)
+    }
+
+    fn write_byte(&mut self, address: usize, byte: u8) {
+        (self.write)(address, byte);
+    }
+}
+
+impl<R, W> Reset for ClosureMem<R, W>
+where
+    R: FnMut(usize) -> u8,
+    W: FnMut(usize, u8),
+{
+    fn reset(&mut self) {}
+}
+
+// Records which addresses have been read and written, for coverage analysis.
+pub struct CoverageMem<M: MemIO> {
+    inner: M,
+    read: Vec<u64>,
+    written: Vec<u64>,
+}
+
+impl<M: MemIO> CoverageMem<M> {
+    pub fn new(inner: M) -> Self {
+        Self {
+            inner,
+            read: vec![0; MAX_MEMORY / 64],
+            written: vec![0; MAX_MEMORY / 64],
+        }
+    }
+
+    pub fn inner(&mut self) -> &mut M {
+        &mut self.inner
+    }
+
+    pub fn read_coverage(&self) -> BTreeSet<usize> {
+        Self::to_set(&self.read)
+    }
+
+    pub fn write_coverage(&self) -> BTreeSet<usize> {
+        Self::to_set(&self.written)
+    }
+
+    fn mark(bitmap: &mut [u64], address: usize) {
+        let address = address % MAX_MEMORY;
+        bitmap[address / 64] |= 1 << (address % 64);
+    }
+
+    fn to_set(bitmap: &[u64]) -> BTreeSet<usize> {
+        (0..MAX_MEMORY)
+            .filter(|address| bitmap[address / 64] >> (address % 64) & 1 == 1)
+            .collect()
+    }
+}
+
+impl<M: MemIO> MemIO for CoverageMem<M> {
+    fn read_byte(&mut self, address: usize) -> u8 {
+        Self::mark(&mut self.read, address);
+        self.inner.read_byte(address)
+    }
+
+    fn read_byte_without_effect(&mut self, address: usize) -> u8 {
+        self.inner.read_byte_without_effect(address)
+    }
+
+    fn write_byte(&mut self, address: usize, byte: u8) {
+        Self::mark(&mut self.written, address);
+        self.inner.write_byte(address, byte);
+    }
+
+    fn is_uninitialized(&self, address: usize) -> bool {
+        self.inner.is_uninitialized(address)
+    }
+}
+
+impl<M: MemIO + Reset> Reset for CoverageMem<M> {
+    fn reset(&mut self) {
+        self.inner.reset();
+    }
+}
+
+// Injects faults at chosen addresses, or on every access after the first N.
+// Fallible accesses report the fault; plain reads see $FF and plain writes are lost.
+pub struct FaultyMem<M: MemIO> {
+    inner: M,
+    faulty: Vec<usize>,
+    fail_after: Option<usize>,
+    accesses: usize,
+}
+
+impl<M: MemIO> FaultyMem<M> {
+    pub fn new(inner: M) -> Self {
+        Self {
+            inner,
+            faulty: vec![],
+            fail_after: None,
+            accesses: 0,
+        }
+    }
+
+    pub fn inner(&mut self) -> &mut M {
+        &mut self.inner
+    }
+
+    pub fn fault_at(&mut self, address: usize) {
+        self.faulty.push(address);
+    }
+
+    pub fn fail_after(&mut self, accesses: usize) {
+        self.fail_after = Some(accesses);
+    }
+
+    fn access(&mut self, address: usize) -> Result<(), MemError> {
+        self.accesses += 1;
+        let exhausted = matches!(self.fail_after, Some(n) if self.accesses > n);
+        if exhausted || self.faulty.contains(&address) {
+            Err(MemError::Fault(address))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl<M: MemIO> MemIO for FaultyMem<M> {
+    fn read_byte(&mut self, address: usize) -> u8 {
+        self.try_read_byte(address).unwrap_or(0xFF)
+    }
+
+    fn read_byte_without_effect(&mut self, address: usize) -> u8 {
+        self.inner.read_byte_without_effect(address)
+    }
+
+    fn write_byte(&mut self, address: usize, byte: u8) {
+        let _ = self.try_write_byte(address, byte);
+    }
+
+    fn try_read_byte(&mut self, address: usize) -> Result<u8, MemError> {
+        self.access(address)?;
+        Ok(self.inner.read_byte(address))
+    }
+
+    fn try_write_byte(&mut self, address: usize, byte: u8) -> Result<(), MemError> {
+        self.access(address)?;
+        self.inner.write_byte(address, byte);
+        Ok(())
+    }
+
+    fn is_uninitialized(&self, address: usize) -> bool {
+        self.inner.is_uninitialized(address)
+    }
+}
+
+impl<M: MemIO + Reset> Reset for FaultyMem<M> {
+    fn reset(&mut self) {
+        self.inner.reset();
+    }
+}
+
+// Counts accesses, and can spin on each one to see how much memory speed matters
+// to a benchmark.
+pub struct SlowMem<M: MemIO> {
+    inner: M,
+    spin: u32,
+    reads: usize,
+    writes: usize,
+}
+
+impl<M: MemIO> SlowMem<M> {
+    pub fn new(inner: M) -> Self {
+        Self {
+            inner,
+            spin: 0,
+            reads: 0,
+            writes: 0,
+        }
+    }
+
+    pub fn inner(&mut self) -> &mut M {
+        &mut self.inner
+    }
+
+    // Busy-waits for `iterations` spins on every read and write.
+    pub fn set_spin(&mut self, iterations: u32) {
+        self.spin = iterations;
+    }
+
+    pub fn reads(&self) -> usize {
+        self.reads
+    }
+
+    pub fn writes(&self) -> usize {
+        self.writes
+    }
+
+    fn delay(&self) {
+        for _ in 0..self.spin {
+            std::hint::spin_loop();
+        }
+    }
+}
+
+impl<M: MemIO> MemIO for SlowMem<M> {
+    fn read_byte(&mut self, address: usize) -> u8 {
+        self.delay();
+        self.reads += 1;
+        self.inner.read_byte(address)
+    }
+
+    fn read_byte_without_effect(&mut self, address: usize) -> u8 {
+        self.inner.read_byte_without_effect(address)
+    }
+
+    fn write_byte(&mut self, address: usize, byte: u8) {
+        self.delay();
+        self.writes += 1;
+        self.inner.write_byte(address, byte);
+    }
+
+    fn is_uninitialized(&self, address: usize) -> bool {
+        self.inner.is_uninitialized(address)
+    }
+}
+
+impl<M: MemIO + Reset> Reset for SlowMem<M> {
+    fn reset(&mut self) {
+        self.inner.reset();
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::cpu::{CpuWarning, CPU};
 
     #[test]
     fn test_index() {
@@ -82,4 +558,201 @@ mod tests {
         assert_eq!(ram[1], 1);
         assert_eq!(ram[2], 2);
     }
+
+    #[test]
+    fn test_persists_across_reset() {
+        let mut cpu = CPU::default();
+        let mut ram = RAM::default();
+        ram[0x0200] = 0x42;
+        ram[0xFFFC] = 0x00;
+        ram[0xFFFD] = 0x80;
+
+        cpu.reset(&mut ram);
+        assert_eq!(cpu.pc, 0x8000);
+        assert_eq!(ram[0x0200], 0x42);
+    }
+
+    #[test]
+    fn test_closure_mem() {
+        let program = [
+            0xAD, 0x34, 0x12, // LDA $1234
+            0x8D, 0x00, 0x02, // STA $0200
+        ];
+        let mut writes = vec![];
+        {
+            let mut mem = ClosureMem::new(
+                |address| match address {
+                    0x8000..=0x8005 => program[address - 0x8000],
+                    _ => 0x42,
+                },
+                |address, byte| writes.push((address, byte)),
+            );
+            let mut cpu = CPU::default();
+            cpu.pc = 0x8000;
+            for _ in 0..8 {
+                cpu.step_unchecked(&mut mem);
+            }
+            assert_eq!(cpu.a, 0x42);
+        }
+        assert_eq!(writes, vec![(0x0200, 0x42)]);
+    }
+
+    #[test]
+    fn test_coverage_mem() {
+        let mut ram = RAM::default();
+        ram.write_rom(
+            0x8000,
+            &[
+                0xA5, 0x10, //       LDA $10
+                0x8D, 0x00, 0x02, // STA $0200
+            ],
+        );
+        let mut mem = CoverageMem::new(ram);
+        let mut cpu = CPU::default();
+        cpu.pc = 0x8000;
+        for _ in 0..7 {
+            cpu.step_unchecked(&mut mem);
+        }
+
+        let read: BTreeSet<usize> = vec![0x10, 0x8000, 0x8001, 0x8002, 0x8003, 0x8004]
+            .into_iter()
+            .collect();
+        assert_eq!(mem.read_coverage(), read);
+        assert_eq!(mem.write_coverage(), vec![0x0200].into_iter().collect());
+    }
+
+    #[test]
+    fn test_load_ihex() {
+        let mut ram = RAM::default();
+        let data = "
+            :03800000A9426032
+            :02FFFC00008083
+            :00000001FF
+        ";
+        assert_eq!(ram.load_ihex(data), Ok(()));
+        assert_eq!(ram[0x8000], 0xA9);
+        assert_eq!(ram[0x8001], 0x42);
+        assert_eq!(ram[0x8002], 0x60);
+        assert_eq!(ram[0xFFFC], 0x00);
+        assert_eq!(ram[0xFFFD], 0x80);
+
+        assert_eq!(
+            ram.load_ihex(":03800000A9426033"),
+            Err(IhexError::ChecksumMismatch(1))
+        );
+        assert_eq!(
+            ram.load_ihex("03800000A9426032"),
+            Err(IhexError::MissingStartCode(1))
+        );
+    }
+
+    #[test]
+    fn test_slow_mem_counts_accesses() {
+        let mut mem = SlowMem::new(RAM::default());
+        mem.set_spin(10);
+        mem.inner().write_rom(0x8000, &[0xB5, 0x10]); // LDA $10,X
+        let mut cpu = CPU::default();
+        cpu.pc = 0x8000;
+        cpu.x = 0x01;
+
+        // opcode, operand, dummy read of $10, read of $11
+        assert_eq!(cpu.step_instruction(&mut mem), 4);
+        assert_eq!(mem.reads(), 4);
+        assert_eq!(mem.writes(), 0);
+    }
+
+    #[test]
+    fn test_faulty_mem() {
+        let mut cpu = CPU::default();
+        let mut mem = FaultyMem::new(RAM::default());
+        mem.inner()[0x10] = 0x42;
+        mem.inner()[0x20] = 0x84;
+        mem.fault_at(0x20);
+
+        assert_eq!(cpu.try_read_byte(&mut mem, 0x10), Ok(0x42));
+        assert_eq!(
+            cpu.try_read_byte(&mut mem, 0x20),
+            Err(MemError::Fault(0x20))
+        );
+        assert_eq!(
+            cpu.try_write_byte(&mut mem, 0x20, 0),
+            Err(MemError::Fault(0x20))
+        );
+        assert_eq!(mem.read_byte(0x20), 0xFF);
+        assert_eq!(mem.inner()[0x20], 0x84);
+
+        mem.fail_after(4);
+        assert_eq!(
+            cpu.try_read_byte(&mut mem, 0x10),
+            Err(MemError::Fault(0x10))
+        );
+    }
+
+    #[test]
+    fn test_changed_ranges() {
+        let before = RAM::default();
+        let mut after = RAM::default();
+        after.write_rom(0x0200, &[1, 2, 3]);
+        after.write_rom(0x0300, &[4]);
+        after[0x0400] = 0; // unchanged
+
+        assert_eq!(
+            before.changed_ranges(&after),
+            vec![(0x0200, vec![1, 2, 3]), (0x0300, vec![4])]
+        );
+        assert_eq!(after.changed_ranges(&after), vec![]);
+    }
+
+    #[test]
+    fn test_uninitialized_read() {
+        let mut cpu = CPU::default();
+        let mut ram = RAM::default();
+        ram.track_uninitialized();
+        ram.write_rom(
+            0x8000,
+            &[
+                0xAD, 0x00, 0x02, // LDA $0200
+                0x8D, 0x00, 0x03, // STA $0300
+                0xAD, 0x00, 0x03, // LDA $0300
+            ],
+        );
+        ram[0xFFFC] = 0x00;
+        cpu.pc = 0x8000;
+
+        assert_eq!(ram.is_uninitialized(0x0200), true);
+        assert_eq!(ram.is_uninitialized(0xFFFC), false);
+        cpu.step_instruction(&mut ram);
+        assert_eq!(
+            cpu.take_warning(),
+            Some(CpuWarning::UninitializedRead {
+                addr: 0x0200,
+                pc: 0x8000
+            })
+        );
+        cpu.step_instruction(&mut ram);
+        cpu.step_instruction(&mut ram);
+        assert_eq!(cpu.take_warning(), None);
+    }
+
+    #[test]
+    fn test_load_rom_truncating() {
+        let mut ram = RAM::default();
+        assert_eq!(ram.load_rom_truncating(0xFFFE, &[1, 2, 3, 4]), 2);
+        assert_eq!((ram[0xFFFE], ram[0xFFFF], ram[0x0000]), (1, 2, 0));
+        assert_eq!(ram.load_rom_truncating(0x10000, &[1]), 0);
+        assert_eq!(ram.load_rom_truncating(0x10001, &[1]), 0);
+        assert_eq!(ram.load_rom_truncating(usize::MAX, &[1]), 0);
+        assert_eq!(ram.load_rom_truncating(0x8000, &[5, 6]), 2);
+        assert_eq!(ram[0x8001], 6);
+    }
+
+    #[test]
+    fn test_load_rom_wrapping() {
+        let mut ram = RAM::default();
+        ram.load_rom_wrapping(0xFFFE, &[1, 2, 3, 4]);
+        assert_eq!(
+            (ram[0xFFFE], ram[0xFFFF], ram[0x0000], ram[0x0001]),
+            (1, 2, 3, 4)
+        );
+    }
 }
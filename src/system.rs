@@ -0,0 +1,204 @@
+use std::collections::VecDeque;
+
+use crate::cpu::{StepError, CPU};
+use crate::ram::{MemIO, RAM};
+
+// A CPU together with its memory, with an optional history for stepping backward.
+pub struct System {
+    pub cpu: CPU,
+    pub ram: RAM,
+    history: VecDeque<Snapshot>,
+    history_len: usize,
+}
+
+// CPU state before a step, plus the old values of the bytes that step wrote
+struct Snapshot {
+    cpu: CPU,
+    writes: Vec<(usize, u8, bool)>, // (address, old byte, was uninitialized)
+}
+
+// Passes accesses through to RAM, remembering what each write overwrote.
+struct WriteRecorder<'a> {
+    ram: &'a mut RAM,
+    writes: Vec<(usize, u8, bool)>,
+}
+
+impl<'a> MemIO for WriteRecorder<'a> {
+    fn read_byte(&mut self, address: usize) -> u8 {
+        self.ram.read_byte(address)
+    }
+
+    fn read_byte_without_effect(&mut self, address: usize) -> u8 {
+        self.ram.read_byte_without_effect(address)
+    }
+
+    fn write_byte(&mut self, address: usize, byte: u8) {
+        let uninitialized = self.ram.is_uninitialized(address);
+        self.writes
+            .push((address, self.ram[address], uninitialized));
+        self.ram.write_byte(address, byte);
+    }
+
+    fn is_uninitialized(&self, address: usize) -> bool {
+        self.ram.is_uninitialized(address)
+    }
+}
+
+impl System {
+    pub fn new(cpu: CPU, ram: RAM) -> Self {
+        Self {
+            cpu,
+            ram,
+            history: VecDeque::new(),
+            history_len: 0,
+        }
+    }
+
+    // Loads `prg` at `load_at`, points the reset vector there and resets the CPU,
+    // running out the reset sequence so the next step executes the first instruction.
+    pub fn boot(prg: &[u8], load_at: u16) -> Self {
+        let mut ram = RAM::default();
+        ram.write_rom(load_at as usize, prg);
+        ram.set_reset_vector(load_at);
+        let mut cpu = CPU::default();
+        cpu.reset(&mut ram);
+        cpu.step_instruction(&mut ram);
+        Self::new(cpu, ram)
+    }
+
+    // Keeps up to `len` steps for `step_back`. 0 disables the history.
+    pub fn set_history_len(&mut self, len: usize) {
+        self.history_len = len;
+        while self.history.len() > len {
+            self.history.pop_front();
+        }
+    }
+
+    // Runs one instruction and returns the cycles it took. Nothing is recorded for
+    // an opcode that can't run.
+    pub fn step(&mut self) -> Result<usize, StepError> {
+        if self.history_len == 0 {
+            return self.cpu.try_step_instruction(&mut self.ram);
+        }
+        let cpu = self.cpu.clone();
+        let mut recorder = WriteRecorder {
+            ram: &mut self.ram,
+            writes: vec![],
+        };
+        let cycles = self.cpu.try_step_instruction(&mut recorder)?;
+        let writes = recorder.writes;
+        if self.history.len() == self.history_len {
+            self.history.pop_front();
+        }
+        self.history.push_back(Snapshot { cpu, writes });
+        Ok(cycles)
+    }
+
+    // Undoes the last recorded step. Returns false if there's nothing to undo.
+    pub fn step_back(&mut self) -> bool {
+        match self.history.pop_back() {
+            Some(snapshot) => {
+                for &(address, byte, uninitialized) in snapshot.writes.iter().rev() {
+                    self.ram.restore_byte(address, byte, uninitialized);
+                }
+                self.cpu.restore(snapshot.cpu);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::CpuWarning;
+
+    #[test]
+    fn test_boot() {
+        let mut system = System::boot(
+            &[
+                0xA9, 0x42, // LDA #$42
+                0xAA, //       TAX
+            ],
+            0x0400,
+        );
+        assert_eq!(system.cpu.pc, 0x0400);
+        system.step().unwrap();
+        system.step().unwrap();
+        assert_eq!(system.cpu.a, 0x42);
+        assert_eq!(system.cpu.x, 0x42);
+        assert_eq!(system.cpu.pc, 0x0403);
+    }
+
+    #[test]
+    fn test_step_back() {
+        let mut ram = RAM::default();
+        ram.write_rom(
+            0x8000,
+            &[
+                0xA9, 0x01, // LDA #$01
+                0x85, 0x10, // STA $10
+                0xE6, 0x10, // INC $10
+                0xE8, //       INX
+            ],
+        );
+        let mut cpu = CPU::default();
+        cpu.pc = 0x8000;
+        let mut system = System::new(cpu, ram);
+        system.set_history_len(8);
+
+        system.step().unwrap();
+        system.step().unwrap();
+        let cpu = system.cpu.clone();
+        assert_eq!(system.ram[0x10], 0x01);
+        system.step().unwrap();
+        system.step().unwrap();
+        assert_eq!(system.ram[0x10], 0x02);
+        assert_eq!(system.cpu.x, 0x01);
+
+        assert_eq!(system.step_back(), true);
+        assert_eq!(system.step_back(), true);
+        assert_eq!(system.cpu.registers(), cpu.registers());
+        assert_eq!(system.ram[0x10], 0x01);
+
+        assert_eq!(system.step_back(), true);
+        assert_eq!(system.ram[0x10], 0x00);
+        assert_eq!(system.step_back(), true);
+        assert_eq!(system.step_back(), false);
+        assert_eq!(system.cpu.pc, 0x8000);
+    }
+
+    #[test]
+    fn test_step_back_uninitialized() {
+        let mut ram = RAM::default();
+        ram.track_uninitialized();
+        ram.write_rom(
+            0x8000,
+            &[
+                0xA5, 0x10, // LDA $10
+                0x85, 0x10, // STA $10
+            ],
+        );
+        let mut cpu = CPU::default();
+        cpu.pc = 0x8000;
+        let mut system = System::new(cpu, ram);
+        system.set_history_len(8);
+
+        system.step().unwrap();
+        assert_eq!(
+            system.cpu.take_warning(),
+            Some(CpuWarning::UninitializedRead {
+                addr: 0x10,
+                pc: 0x8000
+            })
+        );
+        system.step().unwrap();
+        assert_eq!(system.ram.is_uninitialized(0x10), false);
+
+        // undoing the store leaves $10 as unwritten as it was
+        assert_eq!(system.step_back(), true);
+        assert_eq!(system.ram.is_uninitialized(0x10), true);
+        assert_eq!(system.ram.is_uninitialized(0x8000), false);
+    }
+}
@@ -0,0 +1,158 @@
+// Scaffolding for differential testing against another 6502 model: reproducible
+// random machine states, and a runner returning the state after one instruction.
+use crate::cpu::{make_word, Registers, StatusFlag, UnknownOpcodePolicy, CPU};
+use crate::instruction::OPCODES;
+use crate::ram::RAM;
+
+// xorshift64*, small and deterministic for a given seed
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        // the state must never be zero
+        Self(seed ^ 0x9E37_79B9_7F4A_7C15)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    pub fn next_u8(&mut self) -> u8 {
+        (self.next_u64() >> 56) as u8
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MachineState {
+    pub registers: Registers,
+    pub ram: Vec<u8>,
+}
+
+// Produces random states with a defined opcode at PC.
+pub struct CaseGenerator {
+    rng: Rng,
+}
+
+impl CaseGenerator {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            rng: Rng::new(seed),
+        }
+    }
+
+    pub fn next_case(&mut self) -> MachineState {
+        let rng = &mut self.rng;
+        let mut ram: Vec<u8> = (0..0x10000).map(|_| rng.next_u8()).collect();
+        let pc = rng.next_u64() as u16;
+        let mut flags = StatusFlag::default();
+        flags.set_as_u8(rng.next_u8() & 0b11001111);
+        let registers = Registers {
+            pc,
+            sp: rng.next_u8(),
+            a: rng.next_u8(),
+            x: rng.next_u8(),
+            y: rng.next_u8(),
+            flags,
+        };
+        let opcode = loop {
+            let byte = rng.next_u8();
+            if OPCODES[byte as usize].is_some() {
+                break byte;
+            }
+        };
+        ram[pc as usize] = opcode;
+        MachineState { registers, ram }
+    }
+}
+
+// Runs the instruction at PC and returns the resulting state and cycles taken.
+pub fn run_case(case: &MachineState) -> (MachineState, usize) {
+    let mut cpu = CPU::default();
+    cpu.set_registers(case.registers);
+    let mut ram = RAM::new(case.ram.clone());
+    let cycles = cpu.step_instruction(&mut ram);
+    let state = MachineState {
+        registers: cpu.registers(),
+        ram: ram.as_slice().to_vec(),
+    };
+    (state, cycles)
+}
+
+// How many cycles `run_fuzz_input` runs at most
+const FUZZ_CYCLES: usize = 10_000;
+
+// Runs arbitrary bytes as a program: the first two are PC, the rest are loaded there,
+// wrapping around memory. The rest of memory, vectors included, is filled from a hash
+// of the input, so jumps out of the program land on code rather than a run of BRKs.
+// Stops at the first opcode that can't run. This is the body of the fuzz target in
+// `fuzz/`, so it must never panic.
+pub fn run_fuzz_input(data: &[u8]) {
+    if data.len() < 2 {
+        return;
+    }
+    let pc = make_word(data[0], data[1]);
+    let mut ram = RAM::default();
+    // FNV-1a
+    let seed = data.iter().fold(0xCBF2_9CE4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01B3)
+    });
+    ram.randomize(seed);
+    ram.load_rom_wrapping(pc as usize, &data[2..]);
+    let mut cpu = CPU::default();
+    cpu.pc = pc;
+    cpu.unknown_opcode_policy = UnknownOpcodePolicy::Error;
+    for _ in 0..FUZZ_CYCLES {
+        if cpu.halted || cpu.step(&mut ram).is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generator_is_deterministic() {
+        let mut a = CaseGenerator::new(42);
+        let mut b = CaseGenerator::new(42);
+        for _ in 0..4 {
+            assert_eq!(a.next_case(), b.next_case());
+        }
+        assert_ne!(
+            CaseGenerator::new(1).next_case(),
+            CaseGenerator::new(2).next_case()
+        );
+    }
+
+    #[test]
+    fn test_fuzz_corpus() {
+        let corpus = concat!(env!("CARGO_MANIFEST_DIR"), "/fuzz/corpus/step");
+        for entry in std::fs::read_dir(corpus).unwrap() {
+            run_fuzz_input(&std::fs::read(entry.unwrap().path()).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_fuzz_random_inputs() {
+        let mut rng = Rng::new(6502);
+        for _ in 0..64 {
+            let data: Vec<u8> = (0..0x100).map(|_| rng.next_u8()).collect();
+            run_fuzz_input(&data);
+        }
+    }
+
+    #[test]
+    fn test_run_case() {
+        let mut generator = CaseGenerator::new(6502);
+        for _ in 0..64 {
+            let case = generator.next_case();
+            let (state, cycles) = run_case(&case);
+            assert!(cycles >= 2);
+            assert_eq!(state.ram.len(), case.ram.len());
+        }
+    }
+}